@@ -0,0 +1,193 @@
+//! CPU pinning and NUMA placement of worker threads and their buffers.
+use crate::utility::option_enum;
+use std::io::Error as IOError;
+use std::os::raw::{c_uint, c_ulong};
+use std::sync::OnceLock;
+
+const MPOL_BIND: c_ulong = 2;
+const MPOL_MF_MOVE: c_uint = 1 << 1;
+
+//-----------------------------------------------------------------------------
+/// How each worker places its slice of the shared buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NumaPolicy {
+    /// Leave pages wherever the allocating thread touched them.
+    None,
+    /// Worker writes one byte per page of its slice before doing any I/O;
+    /// requires the buffer to be allocated but not initialised.
+    FirstTouch,
+    /// Worker `mbind`s its slice to its local node, moving pages already faulted in.
+    Bind,
+}
+
+option_enum!(NumaPolicy, "NUMA policy", {
+    "none" => None,
+    "first-touch" => FirstTouch,
+    "bind" => Bind,
+});
+
+//-----------------------------------------------------------------------------
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// Read the node layout from `/sys/devices/system/node`; empty if not available.
+pub fn numa_nodes() -> Vec<NumaNode> {
+    let mut nodes = Vec::new();
+    let dir = match std::fs::read_dir("/sys/devices/system/node") {
+        Ok(d) => d,
+        Err(_) => return nodes,
+    };
+    for entry in dir.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id = match name.strip_prefix("node").map(|n| n.parse::<usize>()) {
+            Some(Ok(id)) => id,
+            _ => continue,
+        };
+        let cpus = std::fs::read_to_string(entry.path().join("cpulist"))
+            .ok()
+            .and_then(|l| parse_cpu_list(&l))
+            .unwrap_or_default();
+        nodes.push(NumaNode { id, cpus });
+    }
+    nodes.sort_by_key(|n| n.id);
+    nodes
+}
+
+/// Node hosting `cpu`, if any.
+pub fn node_of_cpu(nodes: &[NumaNode], cpu: usize) -> Option<usize> {
+    nodes.iter().find(|n| n.cpus.contains(&cpu)).map(|n| n.id)
+}
+
+//-----------------------------------------------------------------------------
+/// Parse a kernel style cpu list such as `0-3,8,10-11`; cpus a `cpu_set_t`
+/// cannot hold and reversed ranges are errors.
+pub fn parse_cpu_list(s: &str) -> Option<Vec<usize>> {
+    let cpu = |c: &str| {
+        c.parse::<usize>()
+            .ok()
+            .filter(|&c| c < libc::CPU_SETSIZE as usize)
+    };
+    let mut cpus = Vec::new();
+    for r in s.trim().split(',').filter(|r| !r.is_empty()) {
+        match r.split_once('-') {
+            Some((b, e)) => {
+                let (b, e) = (cpu(b)?, cpu(e)?);
+                if b > e {
+                    return None;
+                }
+                cpus.extend(b..=e);
+            }
+            None => cpus.push(cpu(r)?),
+        }
+    }
+    Some(cpus)
+}
+
+//-----------------------------------------------------------------------------
+/// Pin the calling thread to `cpu`.
+pub fn pin_thread(cpu: usize) -> std::io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(IOError::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Affinity of the process, as inherited by the threads it starts; read once,
+/// from the first caller, before any worker is pinned.
+pub fn initial_affinity() -> libc::cpu_set_t {
+    static INITIAL: OnceLock<libc::cpu_set_t> = OnceLock::new();
+    *INITIAL.get_or_init(|| unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            // unknown: allow every cpu, the kernel drops the ones not present
+            for c in 0..libc::CPU_SETSIZE as usize {
+                libc::CPU_SET(c, &mut set);
+            }
+        }
+        set
+    })
+}
+
+/// Undo `pin_thread`: let the calling thread run on any of the cpus in `set`.
+pub fn unpin_thread(set: &libc::cpu_set_t) -> std::io::Result<()> {
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) } != 0 {
+        return Err(IOError::last_os_error());
+    }
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------
+/// Fault in every page of `buf` from the calling thread.
+pub fn first_touch(buf: &mut [u8]) {
+    let page = page_size::get();
+    let mut i = 0;
+    while i < buf.len() {
+        unsafe { std::ptr::write_volatile(buf.as_mut_ptr().add(i), 0) };
+        i += page;
+    }
+}
+
+/// Bind the pages fully contained in `buf` to `node`, migrating the ones already
+/// allocated. Pages shared with neighbouring slices are left alone.
+pub fn bind_to_node(buf: &[u8], node: usize) -> std::io::Result<()> {
+    let page = page_size::get();
+    let b = (buf.as_ptr() as usize).div_ceil(page) * page;
+    let e = (buf.as_ptr() as usize + buf.len()) / page * page;
+    if e <= b {
+        return Ok(());
+    }
+    let bits = 8 * std::mem::size_of::<c_ulong>();
+    let mut mask = vec![0 as c_ulong; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            b,
+            e - b,
+            MPOL_BIND,
+            mask.as_ptr(),
+            (mask.len() * bits + 1) as c_ulong,
+            MPOL_MF_MOVE,
+        )
+    };
+    if ret != 0 {
+        return Err(IOError::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_list() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list("5"), Some(vec![5]));
+        assert_eq!(parse_cpu_list(" 1,2,\n"), Some(vec![1, 2]));
+        assert_eq!(parse_cpu_list("1,,2"), Some(vec![1, 2]));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+    }
+
+    #[test]
+    fn cpu_list_errors() {
+        assert_eq!(parse_cpu_list("3-1"), None);
+        assert_eq!(parse_cpu_list("a"), None);
+        assert_eq!(parse_cpu_list("1-"), None);
+        assert_eq!(parse_cpu_list("-1"), None);
+        let n = libc::CPU_SETSIZE as usize;
+        assert_eq!(parse_cpu_list(&(n - 1).to_string()), Some(vec![n - 1]));
+        assert_eq!(parse_cpu_list(&n.to_string()), None);
+        assert_eq!(parse_cpu_list(&format!("0-{}", usize::MAX)), None);
+    }
+}
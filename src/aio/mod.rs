@@ -0,0 +1,167 @@
+//! Linux native asynchronous I/O through the raw `io_setup`/`io_submit`/
+//! `io_getevents` system calls; requests only run asynchronously on files
+//! opened with `O_DIRECT`.
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::os::raw::c_long;
+use std::os::unix::io::RawFd;
+
+pub const IOCB_CMD_PREAD: u16 = 0;
+pub const IOCB_CMD_PWRITE: u16 = 1;
+
+//-----------------------------------------------------------------------------
+/// `struct iocb` from `linux/aio_abi.h`, little endian layout.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct IoCb {
+    aio_data: u64,
+    aio_key: u32,
+    aio_rw_flags: i32,
+    aio_lio_opcode: u16,
+    aio_reqprio: i16,
+    aio_fildes: u32,
+    aio_buf: u64,
+    aio_nbytes: u64,
+    aio_offset: i64,
+    aio_reserved2: u64,
+    aio_flags: u32,
+    aio_resfd: u32,
+}
+
+/// `struct io_event` from `linux/aio_abi.h`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct IoEvent {
+    data: u64,
+    obj: u64,
+    res: i64,
+    res2: i64,
+}
+
+//-----------------------------------------------------------------------------
+/// AIO context accepting up to `depth` requests in flight.
+pub struct AioContext {
+    ctx: libc::c_ulong,
+    depth: usize,
+}
+
+impl AioContext {
+    pub fn new(depth: usize) -> std::io::Result<Self> {
+        let mut ctx: libc::c_ulong = 0;
+        let ret = unsafe { libc::syscall(libc::SYS_io_setup, depth as c_long, &mut ctx) };
+        if ret < 0 {
+            return Err(IOError::last_os_error());
+        }
+        Ok(AioContext { ctx, depth })
+    }
+
+    /// Run `opcode` on every `(buffer, length, offset)` request, keeping up to
+    /// `depth` requests in flight; a short transfer is an error. `complete` is
+    /// called with each request once it is done.
+    ///
+    /// # Safety
+    /// The buffers must be valid for the whole call and, with `O_DIRECT`,
+    /// aligned as required by the file system.
+    pub unsafe fn run<I, F>(
+        &self,
+        fd: RawFd,
+        opcode: u16,
+        mut reqs: I,
+        mut complete: F,
+    ) -> std::io::Result<()>
+    where
+        I: Iterator<Item = (*mut u8, usize, u64)>,
+        F: FnMut(*mut u8, usize, u64),
+    {
+        let mut iocbs = vec![IoCb::default(); self.depth];
+        let mut events = vec![IoEvent::default(); self.depth];
+        let mut free: Vec<usize> = (0..self.depth).rev().collect();
+        let mut batch: Vec<*mut IoCb> = Vec::with_capacity(self.depth);
+        let mut in_flight = 0;
+        let mut done = false;
+        loop {
+            batch.clear();
+            while !done && !free.is_empty() {
+                match reqs.next() {
+                    Some((buf, len, offset)) => {
+                        let k = free.pop().unwrap();
+                        iocbs[k] = IoCb {
+                            aio_data: k as u64,
+                            aio_lio_opcode: opcode,
+                            aio_fildes: fd as u32,
+                            aio_buf: buf as u64,
+                            aio_nbytes: len as u64,
+                            aio_offset: offset as i64,
+                            ..IoCb::default()
+                        };
+                        batch.push(iocbs.as_mut_ptr().add(k));
+                    }
+                    None => done = true,
+                }
+            }
+            let mut submitted = 0;
+            while submitted < batch.len() {
+                let n = libc::syscall(
+                    libc::SYS_io_submit,
+                    self.ctx,
+                    (batch.len() - submitted) as c_long,
+                    batch[submitted..].as_ptr(),
+                );
+                if n < 0 {
+                    return Err(IOError::last_os_error());
+                }
+                submitted += n as usize;
+            }
+            in_flight += batch.len();
+            if in_flight == 0 {
+                return Ok(());
+            }
+            let n = libc::syscall(
+                libc::SYS_io_getevents,
+                self.ctx,
+                1 as c_long,
+                self.depth as c_long,
+                events.as_mut_ptr(),
+                std::ptr::null::<libc::timespec>(),
+            );
+            if n < 0 {
+                let err = IOError::last_os_error();
+                if err.kind() == IOErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            for e in &events[..n as usize] {
+                let k = e.data as usize;
+                if e.res < 0 {
+                    return Err(IOError::from_raw_os_error(-e.res as i32));
+                }
+                if e.res as u64 != iocbs[k].aio_nbytes {
+                    return Err(IOError::other(
+                        format!(
+                            "AIO: Failed to transfer data, requested: {}, transferred: {}",
+                            iocbs[k].aio_nbytes, e.res
+                        )
+                        .as_str(),
+                    ));
+                }
+                complete(
+                    iocbs[k].aio_buf as *mut u8,
+                    iocbs[k].aio_nbytes as usize,
+                    iocbs[k].aio_offset as u64,
+                );
+                free.push(k);
+            }
+            in_flight -= n as usize;
+        }
+    }
+}
+
+impl Drop for AioContext {
+    /// Waits for the requests still in flight after an error.
+    fn drop(&mut self) {
+        unsafe {
+            libc::syscall(libc::SYS_io_destroy, self.ctx);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! File to file copy in kernel space, next to a user space copy.
+use iobench::config::ParConfig;
+#[allow(unused_imports)]
+use iobench::kernel_copy::*;
+use iobench::schedule::data_size;
+use iobench::utility::{arg, file_size};
+use iobench::workers::{print_par_timing, ParTiming};
+use std::time::Duration;
+
+type CopyFn = fn(&str, &str, u64) -> std::io::Result<Duration>;
+type ParCopyFn = fn(&str, &str, u64, u64, &ParConfig) -> std::io::Result<ParTiming>;
+
+//-----------------------------------------------------------------------------
+// the method lists are built one feature at a time
+#[allow(clippy::vec_init_then_push)]
+fn main() -> std::io::Result<()> {
+    let src = &arg(1).expect("Missing source file name");
+    let dst = &arg(2).expect("Missing destination file name");
+    let chunk_size = arg(3)
+        .expect("Missing chunk size")
+        .parse::<u64>()
+        .expect("Wrong chunk size");
+    let num_threads = arg(4).map_or(1, |v| v.parse::<u64>().expect("Wrong number of threads"));
+    let mut cfg = ParConfig::from_args()?;
+    let fsize =
+        data_size(src, cfg.variant.files, num_threads, chunk_size)? as f64 / 0x40000000 as f64;
+    println!(
+        "Source: {}, destination: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} thread(s)",
+        src,
+        dst,
+        fsize,
+        chunk_size as f64 / 0x100000 as f64,
+        num_threads
+    );
+    #[allow(unused_mut)]
+    let mut methods: Vec<(&str, CopyFn)> = Vec::new();
+    #[cfg(feature = "seq_copy_pread")]
+    methods.push(("seq_copy_pread", seq_copy_pread));
+    #[cfg(feature = "seq_copy_file_range")]
+    methods.push(("seq_copy_file_range", seq_copy_file_range));
+    #[cfg(feature = "seq_sendfile")]
+    methods.push(("seq_sendfile", seq_sendfile));
+    #[cfg(feature = "seq_splice")]
+    methods.push(("seq_splice", seq_splice));
+    #[cfg(feature = "seq_ficlone")]
+    methods.push(("seq_ficlone", seq_ficlone));
+    #[allow(unused_mut)]
+    let mut par_methods: Vec<(&str, ParCopyFn)> = Vec::new();
+    #[cfg(feature = "par_copy_pread")]
+    par_methods.push(("par_copy_pread", par_copy_pread));
+    #[cfg(feature = "par_copy_file_range")]
+    par_methods.push(("par_copy_file_range", par_copy_file_range));
+    #[cfg(feature = "par_splice")]
+    par_methods.push(("par_splice", par_splice));
+    #[cfg(feature = "par_ficlonerange")]
+    par_methods.push(("par_ficlonerange", par_ficlonerange));
+
+    // a call the file system does not support is reported, not fatal
+    for (name, f) in &methods {
+        // the sequential methods copy `src` itself
+        let fsize = file_size(src)? as f64 / 0x40000000 as f64;
+        match f(src, dst, chunk_size) {
+            Ok(t) => println!(
+                "{:<32} {:.2} GiB/s",
+                format!("{}:", name),
+                fsize / t.as_secs_f64()
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                println!("{:<32} {}", format!("{}:", name), e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    for (name, f) in &par_methods {
+        for v in cfg.variants.clone() {
+            cfg.variant = v;
+            let fsize =
+                data_size(src, v.files, num_threads, chunk_size)? as f64 / 0x40000000 as f64;
+            match f(src, dst, chunk_size, num_threads, &cfg) {
+                Ok(t) => print_par_timing(&cfg.label(name), fsize, &t),
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                    println!("{:<32} {}", format!("{}:", cfg.label(name)), e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! File to file transfer through a local TCP or unix socket.
+use iobench::config::{NetConfig, ParConfig};
+use iobench::net_copy::{net_copy, print_net_timing};
+use iobench::utility::{arg, file_size, fmt_size, parse_sweep};
+
+//-----------------------------------------------------------------------------
+fn main() -> std::io::Result<()> {
+    let src = &arg(1).expect("Missing source file name");
+    let dst = &arg(2).expect("Missing destination file name");
+    // single values or sweeps like "64K..4M" and "1..8"
+    let chunk_sizes = parse_sweep(&arg(3).expect("Missing chunk size")).expect("Wrong chunk size");
+    let stream_counts = arg(4).map_or(vec![1], |v| {
+        parse_sweep(&v).expect("Wrong number of streams")
+    });
+    let cfg = ParConfig::from_args()?;
+    let mut ncfg = NetConfig::from_args()?;
+    let fsize = file_size(src)? as f64 / 0x40000000 as f64;
+    println!(
+        "Source: {}, destination: {}, File size: {:.2} GiB, transport: {}",
+        src, dst, fsize, ncfg.transport
+    );
+    for &chunk_size in &chunk_sizes {
+        for &num_streams in &stream_counts {
+            for s in ncfg.senders.clone() {
+                for r in ncfg.receivers.clone() {
+                    ncfg.sender = s;
+                    ncfg.receiver = r;
+                    let name = format!("net_{}_{}_{}x{}", s, r, fmt_size(chunk_size), num_streams);
+                    // a method not built in is reported, not fatal
+                    match net_copy(src, dst, chunk_size, num_streams, &cfg, &ncfg) {
+                        Ok(t) => print_net_timing(&name, fsize, &t),
+                        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                            println!("{:<32} {}", format!("{}:", name), e)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Parallel file copy.
+use iobench::config::{CopyConfig, ParConfig};
+use iobench::par_copy::par_copy;
+use iobench::schedule::data_size;
+use iobench::utility::arg;
+use iobench::workers::print_par_timing;
+
+//-----------------------------------------------------------------------------
+fn main() -> std::io::Result<()> {
+    let src = &arg(1).expect("Missing source file name");
+    let dst = &arg(2).expect("Missing destination file name");
+    let chunk_size = arg(3)
+        .expect("Missing chunk size")
+        .parse::<u64>()
+        .expect("Wrong chunk size");
+    let num_threads = arg(4).map_or(1, |v| v.parse::<u64>().expect("Wrong number of threads"));
+    let cfg = ParConfig::from_args()?;
+    let ccfg = CopyConfig::from_args()?;
+    let fsize =
+        data_size(src, cfg.variant.files, num_threads, chunk_size)? as f64 / 0x40000000 as f64;
+    println!(
+        "Source: {}, destination: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} thread(s)",
+        src,
+        dst,
+        fsize,
+        chunk_size as f64 / 0x100000 as f64,
+        num_threads
+    );
+    let timing = par_copy(src, dst, chunk_size, num_threads, &cfg, &ccfg)?;
+    let name = format!("par_copy_{}_{}", ccfg.reader, ccfg.writer);
+    print_par_timing(&cfg.label(&name), fsize, &timing.copy);
+    if let Some(t) = &timing.verify {
+        print_par_timing("verify", fsize, t);
+    }
+    Ok(())
+}
@@ -1,68 +1,150 @@
 //! Parallel reading.
 use aligned_vec::*;
+use iobench::affinity::NumaPolicy;
+use iobench::config::ParConfig;
+#[allow(unused_imports)]
+use iobench::consume::*;
 use iobench::par_read::*;
+use iobench::schedule::data_size;
+use iobench::utility::{arg, parse_sweep, print_scaling};
+use iobench::workers::{print_par_timing, ParTiming};
+
+type ParReadFn = fn(&str, u64, u64, &mut [u8], &ParConfig) -> std::io::Result<ParTiming>;
+
 //-----------------------------------------------------------------------------
+// the method lists are built one feature at a time
+#[allow(clippy::vec_init_then_push)]
 fn main() -> std::io::Result<()> {
-    let fname = &std::env::args().nth(1).expect("Missing file name");
-    let chunk_size = std::env::args()
-        .nth(2)
+    let fname = &arg(1).expect("Missing file name");
+    let chunk_size = arg(2)
         .expect("Missing chunk size")
         .parse::<u64>()
         .expect("Wrong file size");
-    let num_threads = std::env::args()
-        .nth(3)
-        .map_or(1, |v| v.parse::<u64>().expect("Wrong num threads number"));
-    let fsize = std::fs::metadata(&fname)?.len() as f64;
+    // either a single thread count or a scaling sweep like "1..32"
+    let thread_counts = arg(3).map_or(vec![1], |v| {
+        parse_sweep(&v).expect("Wrong num threads number")
+    });
+    let mut cfg = ParConfig::from_args()?;
+    // data of each file layout and thread count: with one file per thread
+    // `n` threads read the files `fname.0` to `fname.<n - 1>` only
+    let mut sizes = Vec::new();
+    for v in &cfg.variants {
+        for n in &thread_counts {
+            sizes.push(data_size(fname, v.files, *n, chunk_size)?);
+        }
+    }
+    let fsize = *sizes.iter().max().unwrap() as f64;
+    // leave pages untouched when each worker has to fault in its own slice
+    let init = if cfg.numa == NumaPolicy::FirstTouch {
+        None
+    } else {
+        Some(0)
+    };
     let t = std::time::Instant::now();
-    let mut filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
+    // with a budget smaller than the data each worker reuses a ring of chunks
+    let buf_len = cfg
+        .mem_budget
+        .map_or(fsize as usize, |m| (m as usize).min(fsize as usize));
+    let mut filebuf: Vec<u8> = page_aligned_vec(buf_len, buf_len, init, false);
     println!("Initialization time: {:.2} s", t.elapsed().as_secs_f64());
-    let fsize = fsize / 0x40000000 as f64;
-    println!(
-        "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} thread(s)",
-        fname,
-        fsize,
-        chunk_size as f64 / 0x100000 as f64,
-        num_threads
-    );
+    #[allow(unused_mut)]
+    let mut methods: Vec<(&str, ParReadFn)> = Vec::new();
     #[cfg(feature = "par_read_all")]
-    println!(
-        "par_read_all:\t\t\t {:.2} GiB/s",
-        fsize / par_read_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("par_read_all", par_read_all));
     #[cfg(feature = "par_read_buf_all")]
-    println!(
-        "par_read_buf_all:\t\t {:.2} GiB/s",
-        fsize / par_read_buf_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("par_read_buf_all", par_read_buf_all));
     #[cfg(feature = "par_read_direct_all")]
-    println!(
-        "par_read_direct_all:\t\t {:.2} GiB/s",
-        fsize / par_read_buf_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("par_read_direct_all", par_read_direct_all));
     #[cfg(feature = "par_read_pread_all")]
-    println!(
-        "par_read_pread_all:\t\t {:.2} GiB/s",
-        fsize / par_read_pread_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("par_read_pread_all", par_read_pread_all));
     #[cfg(feature = "par_read_mmap_all")]
-    println!(
-        "par_read_mmap_all:\t\t {:.2} GiB/s",
-        fsize / par_read_mmap_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("par_read_mmap_all", par_read_mmap_all));
     #[cfg(feature = "par_read_vec_all")]
+    methods.push(("par_read_vec_all", par_read_vec_all));
+    #[cfg(all(feature = "par_read_uring_all", target_os = "linux"))]
+    methods.push(("par_read_uring_all", par_read_uring_all));
+    #[cfg(all(feature = "par_read_uring_vec_all", target_os = "linux"))]
+    methods.push(("par_read_uring_vec_all", par_read_uring_vec_all));
+    #[cfg(all(feature = "par_read_preadv2_all", target_os = "linux"))]
+    methods.push(("par_read_preadv2_all", par_read_preadv2_all));
+    #[cfg(all(feature = "par_read_aio_all", target_os = "linux"))]
+    methods.push(("par_read_aio_all", par_read_aio_all));
+    #[cfg(feature = "par_read_posix_aio_all")]
+    methods.push(("par_read_posix_aio_all", par_read_posix_aio_all));
+    #[cfg(feature = "par_read_tokio_all")]
+    methods.push(("par_read_tokio_all", par_read_tokio_all));
+    #[cfg(all(feature = "par_read_tokio_uring_all", target_os = "linux"))]
+    methods.push(("par_read_tokio_uring_all", par_read_tokio_uring_all));
+    #[cfg(feature = "par_glommio_read")]
+    methods.push(("par_glommio_read", par_glommio_read));
+    #[cfg(feature = "par_glommio_dma_read")]
+    methods.push(("par_glommio_dma_read", par_glommio_dma_read));
+    // consumers get the data without copying, the buffer is not used
+    #[cfg(feature = "par_consume_pread")]
+    methods.push((
+        "par_consume_pread",
+        |f: &str, c: u64, n: u64, _: &mut [u8], cfg: &ParConfig| {
+            let size = data_size(f, cfg.variant.files, n, c)?;
+            par_consume_pread(f, c, n, size, cfg, checksum)
+        },
+    ));
+    #[cfg(feature = "par_consume_mmap")]
+    methods.push((
+        "par_consume_mmap",
+        |f: &str, c: u64, n: u64, _: &mut [u8], cfg: &ParConfig| {
+            let size = data_size(f, cfg.variant.files, n, c)?;
+            par_consume_mmap(f, c, n, size, cfg, checksum)
+        },
+    ));
+
+    // with a hash each method runs twice, without and with hashing
+    let hashes = cfg.hashes();
+    if thread_counts.len() == 1 {
+        let num_threads = thread_counts[0];
+        println!(
+            "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} thread(s)",
+            fname,
+            fsize / 0x40000000 as f64,
+            chunk_size as f64 / 0x100000 as f64,
+            num_threads
+        );
+        for (name, f) in &methods {
+            for h in &hashes {
+                cfg.hash = *h;
+                for v in cfg.variants.clone() {
+                    cfg.variant = v;
+                    let fsize = data_size(fname, v.files, num_threads, chunk_size)? as f64
+                        / 0x40000000 as f64;
+                    let timing = f(fname, chunk_size, num_threads, &mut filebuf, &cfg)?;
+                    print_par_timing(&cfg.label(name), fsize, &timing);
+                }
+            }
+        }
+        return Ok(());
+    }
+
     println!(
-        "par_read_vec_all:\t\t {:.2} GiB/s",
-        fsize / par_read_vec_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "par_read_uring_all")]
-    println!(
-    "par_read_uring_all:\t\t {:.2} GiB/s",
-    fsize / par_read_uring_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "par_read_uring_vec_all")]
-    println!(
-        "par_read_iouring_vec_all:\t\t {:.2} GiB/s",
-        fsize / par_read_uring_vec_all(fname, chunk_size, num_threads, &mut filebuf)?.as_secs_f64()
+        "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {:?} threads",
+        fname,
+        fsize / 0x40000000 as f64,
+        chunk_size as f64 / 0x100000 as f64,
+        thread_counts
     );
+    for (name, f) in &methods {
+        for h in &hashes {
+            cfg.hash = *h;
+            for v in cfg.variants.clone() {
+                cfg.variant = v;
+                let mut results = Vec::new();
+                for n in &thread_counts {
+                    let fsize =
+                        data_size(fname, v.files, *n, chunk_size)? as f64 / 0x40000000 as f64;
+                    let d = f(fname, chunk_size, *n, &mut filebuf, &cfg)?;
+                    results.push((*n, fsize / d.elapsed().as_secs_f64()));
+                }
+                print_scaling(&cfg.label(name), &results);
+            }
+        }
+    }
     Ok(())
 }
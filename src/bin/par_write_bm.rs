@@ -1,87 +1,114 @@
 //! Parallel reading.
 use aligned_vec::*;
+use iobench::config::ParConfig;
 use iobench::par_write::*;
+use iobench::utility::{arg, parse_sweep, print_scaling};
+use iobench::workers::{print_par_timing, ParTiming};
+
+type ParWriteFn = fn(&str, u64, u64, u64, &[u8], &ParConfig) -> std::io::Result<ParTiming>;
+
 //-----------------------------------------------------------------------------
+// the method lists are built one feature at a time
+#[allow(clippy::vec_init_then_push)]
 fn main() -> std::io::Result<()> {
-    let fname = &std::env::args().nth(1).expect("Missing file name");
-    let chunk_size = std::env::args()
-        .nth(2)
+    let fname = &arg(1).expect("Missing file name");
+    let chunk_size = arg(2)
         .expect("Missing chunk size")
         .parse::<u64>()
         .expect("Wrong file size");
-    let num_chunks = std::env::args()
-        .nth(3)
+    let num_chunks = arg(3)
         .expect("Missing number of chunks")
         .parse::<u64>()
         .expect("Wrong number of chunks");
-    let num_threads = std::env::args()
-        .nth(4)
-        .map_or(1, |v| v.parse::<u64>().expect("Wrong num threads number"));
+    // either a single thread count or a scaling sweep like "1..32"
+    let thread_counts = arg(4).map_or(vec![1], |v| {
+        parse_sweep(&v).expect("Wrong num threads number")
+    });
+    let mut cfg = ParConfig::from_args()?;
     let fsize = num_chunks * chunk_size;
+    // with a budget smaller than the data each worker reuses a ring of chunks
+    let buf_len = cfg.mem_budget.map_or(fsize, |m| m.min(fsize)) as usize;
     let t = std::time::Instant::now();
-    let filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
+    let filebuf: Vec<u8> = page_aligned_vec(buf_len, buf_len, Some(0), false);
     println!("Initialization time: {:.2} s", t.elapsed().as_secs_f64());
     let fsize = fsize as f64 / 0x40000000 as f64;
-    println!(
-        "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} thread(s)",
-        fname,
-        fsize,
-        chunk_size as f64 / 0x100000 as f64,
-        num_threads
-    );
+    #[allow(unused_mut)]
+    let mut methods: Vec<(&str, ParWriteFn)> = Vec::new();
     #[cfg(feature = "par_write_all")]
-    println!(
-        "par_write_all:\t\t\t {:.2} GiB/s",
-        fsize / par_write_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?.as_secs_f64()
-    );
+    methods.push(("par_write_all", par_write_all));
     #[cfg(feature = "par_write_buf_all")]
-    println!(
-        "par_write_buf_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_buf_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
-    );
+    methods.push(("par_write_buf_all", par_write_buf_all));
     #[cfg(feature = "par_write_direct_all")]
-    println!(
-        "par_write_direct_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_direct_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
-    );
+    methods.push(("par_write_direct_all", par_write_direct_all));
     #[cfg(feature = "par_write_pwrite_all")]
-    println!(
-        "par_write_pwrite_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_pwrite_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
-    );
+    methods.push(("par_write_pwrite_all", par_write_pwrite_all));
     #[cfg(feature = "par_write_mmap_all")]
-    println!(
-        "par_write_mmap_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_mmap_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
-    );
+    methods.push(("par_write_mmap_all", par_write_mmap_all));
     #[cfg(feature = "par_write_vec_all")]
-    println!(
-        "par_write_vec_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_vec_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
-    );
+    methods.push(("par_write_vec_all", par_write_vec_all));
     #[cfg(all(feature = "par_write_uring_all", target_os = "linux"))]
-    println!(
-        "par_write_uring_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_uring_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
-    );
+    methods.push(("par_write_uring_all", par_write_uring_all));
     #[cfg(all(feature = "par_write_uring_vec_all", target_os = "linux"))]
+    methods.push(("par_write_uring_vec_all", par_write_uring_vec_all));
+    #[cfg(all(feature = "par_write_pwritev2_all", target_os = "linux"))]
+    methods.push(("par_write_pwritev2_all", par_write_pwritev2_all));
+    #[cfg(all(feature = "par_write_aio_all", target_os = "linux"))]
+    methods.push(("par_write_aio_all", par_write_aio_all));
+    #[cfg(feature = "par_write_posix_aio_all")]
+    methods.push(("par_write_posix_aio_all", par_write_posix_aio_all));
+    #[cfg(feature = "par_write_tokio_all")]
+    methods.push(("par_write_tokio_all", par_write_tokio_all));
+    #[cfg(all(feature = "par_write_tokio_uring_all", target_os = "linux"))]
+    methods.push(("par_write_tokio_uring_all", par_write_tokio_uring_all));
+    #[cfg(feature = "par_glommio_write")]
+    methods.push(("par_glommio_write", par_glommio_write));
+    #[cfg(feature = "par_glommio_dma_write")]
+    methods.push(("par_glommio_dma_write", par_glommio_dma_write));
+
+    // with a hash each method runs twice, without and with hashing
+    let hashes = cfg.hashes();
+    if thread_counts.len() == 1 {
+        let num_threads = thread_counts[0];
+        println!(
+            "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} thread(s)",
+            fname,
+            fsize,
+            chunk_size as f64 / 0x100000 as f64,
+            num_threads
+        );
+        for (name, f) in &methods {
+            for h in &hashes {
+                cfg.hash = *h;
+                for v in cfg.variants.clone() {
+                    cfg.variant = v;
+                    let timing = f(fname, chunk_size, num_chunks, num_threads, &filebuf, &cfg)?;
+                    print_par_timing(&cfg.label(name), fsize, &timing);
+                }
+            }
+        }
+        return Ok(());
+    }
+
     println!(
-        "par_write_uring_vec_all:\t\t {:.2} GiB/s",
-        fsize
-            / par_write_uring_vec_all(fname, chunk_size, num_chunks, num_threads, &filebuf)?
-                .as_secs_f64()
+        "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {:?} threads",
+        fname,
+        fsize,
+        chunk_size as f64 / 0x100000 as f64,
+        thread_counts
     );
+    for (name, f) in &methods {
+        for h in &hashes {
+            cfg.hash = *h;
+            for v in cfg.variants.clone() {
+                cfg.variant = v;
+                let mut results = Vec::new();
+                for n in &thread_counts {
+                    let d = f(fname, chunk_size, num_chunks, *n, &filebuf, &cfg)?;
+                    results.push((*n, fsize / d.elapsed().as_secs_f64()));
+                }
+                print_scaling(&cfg.label(name), &results);
+            }
+        }
+    }
     Ok(())
 }
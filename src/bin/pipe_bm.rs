@@ -0,0 +1,66 @@
+//! Read/compute pipeline.
+use iobench::config::{Compute, ParConfig, PipeConfig};
+use iobench::consume::{checksum, spin};
+use iobench::pipeline::{pipeline, print_pipe_timing};
+use iobench::schedule::data_size;
+use iobench::utility::arg;
+
+//-----------------------------------------------------------------------------
+fn main() -> std::io::Result<()> {
+    let fname = &arg(1).expect("Missing file name");
+    let chunk_size = arg(2)
+        .expect("Missing chunk size")
+        .parse::<u64>()
+        .expect("Wrong chunk size");
+    let num_readers = arg(3).map_or(1, |v| v.parse::<u64>().expect("Wrong number of readers"));
+    let num_computers = arg(4).map_or(1, |v| {
+        v.parse::<u64>().expect("Wrong number of compute threads")
+    });
+    let mut cfg = ParConfig::from_args()?;
+    let mut pcfg = PipeConfig::from_args()?;
+    let fsize =
+        data_size(fname, cfg.variant.files, num_readers, chunk_size)? as f64 / 0x40000000 as f64;
+    println!(
+        "File: {}, File size: {:.2} GiB, chunk size: {:.2} MiB, {} reader(s), {} compute thread(s), compute: {}",
+        fname,
+        fsize,
+        chunk_size as f64 / 0x100000 as f64,
+        num_readers,
+        num_computers,
+        pcfg.compute
+    );
+    let hashes = cfg.hashes();
+    for source in pcfg.sources.clone() {
+        pcfg.source = source;
+        for h in &hashes {
+            cfg.hash = *h;
+            for v in cfg.variants.clone() {
+                cfg.variant = v;
+                let fsize =
+                    data_size(fname, v.files, num_readers, chunk_size)? as f64 / 0x40000000 as f64;
+                let timing = match pcfg.compute {
+                    Compute::Checksum => pipeline(
+                        fname,
+                        chunk_size,
+                        num_readers,
+                        num_computers,
+                        &cfg,
+                        &pcfg,
+                        checksum,
+                    )?,
+                    Compute::Spin(r) => pipeline(
+                        fname,
+                        chunk_size,
+                        num_readers,
+                        num_computers,
+                        &cfg,
+                        &pcfg,
+                        spin(r),
+                    )?,
+                };
+                print_pipe_timing(&cfg.label(&format!("pipeline_{}", source)), fsize, &timing);
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,73 +1,184 @@
 //! Read/Write files using a variety of APIs in serial and parallel mode
 use aligned_vec::*;
+use iobench::config::{hash, mem_budget, Hash, MmapConfig};
+#[allow(unused_imports)]
+use iobench::consume::*;
+use iobench::digest::Digests;
 use iobench::read::*;
+use iobench::utility::{fmt_size, parse_sweep, MmapTiming};
+use std::time::Duration;
+
+type ReadFn = fn(&str, u64, &mut [u8], &Digests) -> std::io::Result<Duration>;
+// mmap methods also report the time spent mapping and prefaulting the file
+type MmapReadFn = fn(&str, u64, &mut [u8], &MmapConfig, &Digests) -> std::io::Result<MmapTiming>;
+
+// method name, with the hash when hashing each chunk
+fn label(name: &str, hash: Option<Hash>) -> String {
+    hash.map_or(name.to_string(), |h| format!("{}+{}", name, h))
+}
+
 //-----------------------------------------------------------------------------
+// the method lists are built one feature at a time
+#[allow(clippy::vec_init_then_push)]
 fn main() -> std::io::Result<()> {
     let fname = &std::env::args().nth(1).expect("Missing file name");
-    let chunk_size = std::env::args()
-        .nth(2)
-        .expect("Missing chunk size")
-        .parse::<u64>()
-        .expect("Wrong file size");
-    let fsize = std::fs::metadata(&fname)?.len() as f64;
+    // either a single chunk size or a sweep like "4K..64M x2"
+    let chunk_sizes = parse_sweep(&std::env::args().nth(2).expect("Missing chunk size"))
+        .expect("Wrong chunk size");
+    let mmap_cfg = MmapConfig::from_args()?;
+    // with a hash each method runs twice, without and with hashing
+    let hashes = match hash()? {
+        Some(h) => vec![None, Some(h)],
+        None => vec![None],
+    };
+    let size = std::fs::metadata(fname)?.len();
+    let fsize = size as f64;
+    // with a budget smaller than the file the buffer is reused as a ring
+    let buf_len = mem_budget()?.map_or(fsize as usize, |m| (m as usize).min(fsize as usize));
     let t = std::time::Instant::now();
-    let mut filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
+    let mut filebuf: Vec<u8> = page_aligned_vec(buf_len, buf_len, Some(0), false);
     println!("Initialization time: {:.2} s", t.elapsed().as_secs_f64());
     let fsize = fsize / 0x40000000 as f64;
-    println!(
-        "File size: {:.2} GiB, chunk size: {:.2} MiB",
-        fsize,
-        chunk_size as f64 / 0x100000 as f64
-    );
+    #[allow(unused_mut)]
+    let mut methods: Vec<(&str, ReadFn)> = Vec::new();
     #[cfg(feature = "seq_read")]
-    println!(
-        "seq_read:\t\t\t {:.2} GiB/s",
-        fsize / seq_read(fname, chunk_size)?.as_secs_f64()
-    );
+    methods.push(("seq_read", |f: &str, c: u64, _: &mut [u8], d: &Digests| {
+        seq_read(f, c, d)
+    }));
     #[cfg(feature = "seq_read_all")]
-    println!(
-        "seq_read_all:\t\t\t {:.2} GiB/s",
-        fsize / seq_read_all(fname, chunk_size, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("seq_read_all", seq_read_all));
     #[cfg(feature = "seq_read_direct_all")]
-    println!(
-        "seq_read_direct_all:\t\t {:.2} GiB/s",
-        fsize / seq_read_direct_all(fname, chunk_size, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("seq_read_direct_all", seq_read_direct_all));
     #[cfg(feature = "seq_read_buf")]
-    println!(
-        "seq_read_buf:\t\t\t {:.2} GiB/s",
-        fsize / seq_read_buf(fname, chunk_size)?.as_secs_f64()
-    );
+    methods.push((
+        "seq_read_buf",
+        |f: &str, c: u64, _: &mut [u8], d: &Digests| seq_read_buf(f, c, d),
+    ));
     #[cfg(feature = "seq_read_buf_all")]
-    println!(
-        "seq_read_buf_all:\t\t {:.2} GiB/s",
-        fsize / seq_read_buf_all(fname, chunk_size, &mut filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_read_mmap")]
-    println!(
-        "seq_read_mmap:\t\t\t {:.2} GiB/s",
-        fsize / seq_read_mmap(fname, chunk_size)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_read_mmap_all")]
-    println!(
-        "seq_read_mmap_all:\t\t {:.2} GiB/s",
-        fsize / seq_read_mmap_all(fname, chunk_size, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("seq_read_buf_all", seq_read_buf_all));
     #[cfg(feature = "seq_read_vec_all")]
-    println!(
-        "seq_read_vec_all:\t\t {:.2} GiB/s",
-        fsize / seq_read_vec_all(fname, chunk_size, &mut filebuf)?.as_secs_f64()
-    );
+    methods.push(("seq_read_vec_all", seq_read_vec_all));
     #[cfg(feature = "seq_glommio_read")]
-    println!(
-        "seq_glommio_read:\t\t {:.2} GiB/s",
-        fsize / seq_glommio_read(fname, chunk_size)?.as_secs_f64()
-    );
+    methods.push(("seq_glommio_read", seq_glommio_read));
     #[cfg(feature = "async_glommio_read")]
+    methods.push(("async_glommio_read", async_glommio_read));
+    #[cfg(feature = "seq_glommio_dma_read")]
+    methods.push(("seq_glommio_dma_read", seq_glommio_dma_read));
+    #[cfg(feature = "seq_consume_read")]
+    methods.push((
+        "seq_consume_read",
+        |f: &str, c: u64, _: &mut [u8], d: &Digests| seq_consume_read(f, c, d, checksum),
+    ));
+    #[allow(unused_mut)]
+    let mut mmap_methods: Vec<(&str, MmapReadFn)> = Vec::new();
+    #[cfg(feature = "seq_read_mmap")]
+    mmap_methods.push((
+        "seq_read_mmap",
+        |f: &str, c: u64, _: &mut [u8], m: &MmapConfig, d: &Digests| seq_read_mmap(f, c, m, d),
+    ));
+    #[cfg(feature = "seq_read_mmap_all")]
+    mmap_methods.push(("seq_read_mmap_all", seq_read_mmap_all));
+    #[cfg(feature = "seq_consume_mmap")]
+    mmap_methods.push((
+        "seq_consume_mmap",
+        |f: &str, c: u64, _: &mut [u8], m: &MmapConfig, d: &Digests| {
+            seq_consume_mmap(f, c, m, d, checksum)
+        },
+    ));
+
+    if chunk_sizes.len() == 1 {
+        let chunk_size = chunk_sizes[0];
+        println!(
+            "File size: {:.2} GiB, chunk size: {:.2} MiB",
+            fsize,
+            chunk_size as f64 / 0x100000 as f64
+        );
+        for (name, f) in &methods {
+            for h in &hashes {
+                let d = Digests::new(*h, size, chunk_size)?;
+                println!(
+                    "{:<32} {:.2} GiB/s",
+                    format!("{}:", label(name, *h)),
+                    fsize / f(fname, chunk_size, &mut filebuf, &d)?.as_secs_f64()
+                );
+                print_digest(&d);
+            }
+        }
+        for (name, f) in &mmap_methods {
+            for h in &hashes {
+                let d = Digests::new(*h, size, chunk_size)?;
+                let t = f(fname, chunk_size, &mut filebuf, &mmap_cfg, &d)?;
+                println!(
+                    "{:<32} {:.2} GiB/s, prefault: {:.3} s, copy: {:.3} s",
+                    format!("{}:", label(name, *h)),
+                    fsize / t.elapsed().as_secs_f64(),
+                    t.prefault.as_secs_f64(),
+                    t.copy.as_secs_f64()
+                );
+                print_digest(&d);
+            }
+        }
+        return Ok(());
+    }
+
+    // sweep: same buffer reused for all the chunk sizes, one row per method
     println!(
-        "async_glommio_read:\t\t {:.2} GiB/s",
-        fsize / async_glommio_read(fname, chunk_size)?.as_secs_f64()
+        "File size: {:.2} GiB, chunk sizes: {}..{} ({} steps), GiB/s",
+        fsize,
+        fmt_size(chunk_sizes[0]),
+        fmt_size(*chunk_sizes.last().unwrap()),
+        chunk_sizes.len()
     );
+    print!("{:<24}", "method");
+    for c in &chunk_sizes {
+        print!("{:>10}", fmt_size(*c));
+    }
+    println!();
+    for (name, f) in &methods {
+        for h in &hashes {
+            print!("{:<24}", label(name, *h));
+            for c in &chunk_sizes {
+                let d = Digests::new(*h, size, *c)?;
+                print!(
+                    "{:>10.2}",
+                    fsize / f(fname, *c, &mut filebuf, &d)?.as_secs_f64()
+                );
+            }
+            println!();
+        }
+    }
+    // one more row per mmap method with the prefault time in seconds
+    for (name, f) in &mmap_methods {
+        for h in &hashes {
+            let t: Vec<MmapTiming> = chunk_sizes
+                .iter()
+                .map(|c| {
+                    f(
+                        fname,
+                        *c,
+                        &mut filebuf,
+                        &mmap_cfg,
+                        &Digests::new(*h, size, *c)?,
+                    )
+                })
+                .collect::<std::io::Result<_>>()?;
+            print!("{:<24}", label(name, *h));
+            for t in &t {
+                print!("{:>10.2}", fsize / t.elapsed().as_secs_f64());
+            }
+            println!();
+            print!("{:<24}", "  prefault (s)");
+            for t in &t {
+                print!("{:>10.3}", t.prefault.as_secs_f64());
+            }
+            println!();
+        }
+    }
     Ok(())
 }
+
+fn print_digest(d: &Digests) {
+    if let Some(d) = d.chunked_digest() {
+        println!("{:<32} {}", "  chunked digest:", d);
+    }
+}
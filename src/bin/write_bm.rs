@@ -1,5 +1,7 @@
 //! Read/Write files using a variety of APIs in serial and parallel mode
 use aligned_vec::*;
+use iobench::config::{hash, mem_budget, Hash, MmapConfig};
+use iobench::digest::Digests;
 use iobench::write::*;
 //-----------------------------------------------------------------------------
 fn main() -> std::io::Result<()> {
@@ -14,65 +16,146 @@ fn main() -> std::io::Result<()> {
         .expect("Missing number of chunks")
         .parse::<u64>()
         .expect("Wrong number of chunk size");
+    #[allow(unused_variables)]
+    let mmap_cfg = MmapConfig::from_args()?;
+    let hashes = match hash()? {
+        Some(h) => vec![None, Some(h)],
+        None => vec![None],
+    };
     let fsize = num_chunks * chunk_size;
+    // with a budget smaller than the file the buffer is reused as a ring
+    let buf_len = mem_budget()?.map_or(fsize, |m| m.min(fsize)) as usize;
     let t = std::time::Instant::now();
-    let filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
+    let filebuf: Vec<u8> = page_aligned_vec(buf_len, buf_len, Some(0), false);
     println!("Initialization time: {:.2} s", t.elapsed().as_secs_f64());
+    let size = fsize;
     let fsize = fsize as f64 / 0x40000000 as f64;
     println!(
         "File size: {:.2} GiB, chunk size: {:.2} MiB",
         fsize,
         chunk_size as f64 / 0x100000 as f64
     );
-    #[cfg(feature = "seq_write")]
-    println!(
-        "seq_write:\t\t\t {:.2} GiB/s",
-        fsize / seq_write(fname, chunk_size, num_chunks)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_all")]
-    println!(
-        "seq_write_all:\t\t\t {:.2} GiB/s",
-        fsize / seq_write_all(fname, chunk_size, num_chunks, &filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_direct_all")]
-    println!(
-        "seq_write_direct_all:\t\t {:.2} GiB/s",
-        fsize / seq_write_direct_all(fname, chunk_size, num_chunks, &filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_buf")]
-    println!(
-        "seq_write_buf:\t\t\t {:.2} GiB/s",
-        fsize / seq_write_buf(fname, chunk_size, num_chunks)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_buf_all")]
-    println!(
-        "seq_write_buf_all:\t\t {:.2} GiB/s",
-        fsize / seq_write_buf_all(fname, chunk_size, num_chunks, &filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_mmap")]
-    println!(
-        "seq_write_mmap:\t\t\t {:.2} GiB/s",
-        fsize / seq_write_mmap(fname, chunk_size, num_chunks)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_mmap_all")]
-    println!(
-        "seq_write_mmap_all:\t\t {:.2} GiB/s",
-        fsize / seq_write_mmap_all(fname, chunk_size, num_chunks, &filebuf)?.as_secs_f64()
-    );
-    #[cfg(feature = "seq_write_vec_all")]
-    println!(
-        "seq_write_vec_all:\t\t {:.2} GiB/s",
-        fsize / seq_write_vec_all(fname, chunk_size, &filebuf)?.as_secs_f64()
-    );
-    #[cfg(all(feature = "seq_write_uring_all", target_os = "linux"))]
-    println!(
-        "seq_write_uring_all:\t\t {:.2} GiB/s",
-        fsize / seq_write_uring_all(fname, chunk_size, num_chunks)?.as_secs_f64()
-    );
-    #[cfg(all(feature = "seq_write_uring_vec_all", target_os = "linux"))]
+    // with a hash each method runs twice, without and with hashing
+    for h in &hashes {
+        #[allow(unused_variables)]
+        let d = Digests::new(*h, size, chunk_size)?;
+        #[cfg(feature = "seq_write")]
+        report(
+            "seq_write",
+            *h,
+            fsize / seq_write(fname, chunk_size, num_chunks, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_write_all")]
+        report(
+            "seq_write_all",
+            *h,
+            fsize / seq_write_all(fname, chunk_size, num_chunks, &filebuf, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_write_direct_all")]
+        report(
+            "seq_write_direct_all",
+            *h,
+            fsize
+                / seq_write_direct_all(fname, chunk_size, num_chunks, &filebuf, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_write_buf")]
+        report(
+            "seq_write_buf",
+            *h,
+            fsize / seq_write_buf(fname, chunk_size, num_chunks, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_write_buf_all")]
+        report(
+            "seq_write_buf_all",
+            *h,
+            fsize / seq_write_buf_all(fname, chunk_size, num_chunks, &filebuf, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_write_mmap")]
+        {
+            let t = seq_write_mmap(fname, chunk_size, num_chunks, &mmap_cfg, &d)?;
+            println!(
+                "{:<32} {:.2} GiB/s, writeback: {:.3} s",
+                format!("{}:", label("seq_write_mmap", *h)),
+                fsize / t.elapsed().as_secs_f64(),
+                t.writeback.as_secs_f64()
+            );
+            print_digest(&d);
+        }
+        #[cfg(feature = "seq_write_mmap_all")]
+        {
+            let t = seq_write_mmap_all(fname, chunk_size, num_chunks, &filebuf, &mmap_cfg, &d)?;
+            println!(
+                "{:<32} {:.2} GiB/s, writeback: {:.3} s",
+                format!("{}:", label("seq_write_mmap_all", *h)),
+                fsize / t.elapsed().as_secs_f64(),
+                t.writeback.as_secs_f64()
+            );
+            print_digest(&d);
+        }
+        #[cfg(feature = "seq_write_vec_all")]
+        report(
+            "seq_write_vec_all",
+            *h,
+            fsize / seq_write_vec_all(fname, chunk_size, num_chunks, &filebuf, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_glommio_write")]
+        report(
+            "seq_glommio_write",
+            *h,
+            fsize / seq_glommio_write(fname, chunk_size, num_chunks, &filebuf, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(feature = "seq_glommio_dma_write")]
+        report(
+            "seq_glommio_dma_write",
+            *h,
+            fsize
+                / seq_glommio_dma_write(fname, chunk_size, num_chunks, &filebuf, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(all(feature = "seq_write_uring_all", target_os = "linux"))]
+        report(
+            "seq_write_uring_all",
+            *h,
+            fsize / seq_write_uring_all(fname, chunk_size, num_chunks, &d)?.as_secs_f64(),
+            &d,
+        );
+        #[cfg(all(feature = "seq_write_uring_vec_all", target_os = "linux"))]
+        report(
+            "seq_write_uring_vec_all",
+            *h,
+            fsize
+                / seq_write_uring_vec_all(fname, chunk_size, num_chunks, &filebuf, &d)?
+                    .as_secs_f64(),
+            &d,
+        );
+    }
+    Ok(())
+}
+
+// method name, with the hash when hashing each chunk
+fn label(name: &str, hash: Option<Hash>) -> String {
+    hash.map_or(name.to_string(), |h| format!("{}+{}", name, h))
+}
+
+#[allow(dead_code)]
+fn report(name: &str, hash: Option<Hash>, gib_s: f64, d: &Digests) {
     println!(
-        "seq_write_uring_vec_all:\t {:.2} GiB/s",
-        fsize / seq_write_uring_vec_all(fname, chunk_size, num_chunks, &filebuf)?.as_secs_f64()
+        "{:<32} {:.2} GiB/s",
+        format!("{}:", label(name, hash)),
+        gib_s
     );
-    Ok(())
+    print_digest(d);
+}
+
+fn print_digest(d: &Digests) {
+    if let Some(d) = d.chunked_digest() {
+        println!("{:<32} {}", "  chunked digest:", d);
+    }
 }
@@ -0,0 +1,649 @@
+//! Run-time options shared by the engines.
+use crate::affinity::{numa_nodes, parse_cpu_list, NumaNode, NumaPolicy};
+use crate::pool::ThreadPool;
+use crate::schedule::{Files, Partition, Schedule};
+use crate::utility::{opt_arg, option_enum, parse_madvise, parse_rwf, parse_size};
+use crate::workers::Mode;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+
+//-----------------------------------------------------------------------------
+/// One combination of the options that can be compared in a single report.
+#[derive(Clone, Copy, Debug)]
+pub struct Variant {
+    pub schedule: Schedule,
+    pub partition: Partition,
+    pub files: Files,
+    pub mode: Mode,
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Variant {
+            schedule: Schedule::Static,
+            partition: Partition::Contiguous,
+            files: Files::Shared,
+            mode: Mode::Threads,
+        }
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {}, {}, {}",
+            self.schedule, self.partition, self.files, self.mode
+        )
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// When the mmap writers flush the dirty pages of the mapping.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Flush {
+    /// After every chunk, only the chunk's range.
+    Chunk,
+    /// Once per worker after all its chunks, the ranges it wrote.
+    Thread,
+    /// Once after all the workers end.
+    #[default]
+    End,
+}
+
+option_enum!(Flush, "flush granularity", {
+    "chunk" => Chunk,
+    "thread" => Thread,
+    "end" => End,
+});
+
+//-----------------------------------------------------------------------------
+/// Hash of the per-chunk hashing stage of the engines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Hash {
+    Crc32c,
+    Xxh3,
+    Sha256,
+}
+
+option_enum!(Hash, "hash", {
+    "crc32c" => Crc32c,
+    "xxh3" => Xxh3,
+    "sha256" => Sha256,
+});
+
+//-----------------------------------------------------------------------------
+/// How the reader stage of the pipeline fills each buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    /// `pread` into the buffer.
+    Pread,
+    /// `pread` from a file opened with `O_DIRECT`.
+    Direct,
+    /// Copy from a mapping of the file, mapped as selected by `MmapConfig`.
+    Mmap,
+    /// `preadv2` with the `--rwf` flags.
+    Preadv2,
+}
+
+option_enum!(Source, "reader", {
+    "pread" => Pread,
+    "direct" => Direct,
+    "mmap" => Mmap,
+    "preadv2" => Preadv2,
+});
+
+/// Work done by the compute stage of the pipeline on each buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Compute {
+    /// Sum of the 64 bit words, see `consume::checksum`.
+    #[default]
+    Checksum,
+    /// Busy wait for the time taken to process the buffer at the given rate
+    /// in MiB/s.
+    Spin(u64),
+}
+
+impl std::str::FromStr for Compute {
+    type Err = IOError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "checksum" => return Ok(Compute::Checksum),
+            Some(("spin", r)) => {
+                if let Ok(r @ 1..) = r.parse::<u64>() {
+                    return Ok(Compute::Spin(r));
+                }
+            }
+            _ => {}
+        }
+        Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            format!("Unknown compute '{}', use checksum|spin:<MiB/s>", s).as_str(),
+        ))
+    }
+}
+
+impl std::fmt::Display for Compute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compute::Checksum => write!(f, "checksum"),
+            Compute::Spin(r) => write!(f, "spin:{}", r),
+        }
+    }
+}
+
+/// Stages of the read/compute pipeline. Readers issue one blocking read per
+/// buffer, so only the synchronous sources are supported: `pread`, `direct`,
+/// `mmap` and `preadv2`; the AIO, io_uring and tokio engines keep several
+/// requests in flight and are only available in `par_read`.
+#[derive(Clone, Debug)]
+pub struct PipeConfig {
+    /// Reader method of the current run.
+    pub source: Source,
+    /// All the reader methods requested on the command line, one run each.
+    pub sources: Vec<Source>,
+    pub compute: Compute,
+    /// Chunk buffers shared by the two stages, two per reader when not set.
+    pub buffers: Option<usize>,
+}
+
+impl PipeConfig {
+    /// Build from the command line options:
+    /// - `--reader=pread|direct|mmap|preadv2[,...]`: reader stage methods
+    /// - `--compute=checksum|spin:<MiB/s>`: compute stage work
+    /// - `--buffers=<n>`: buffers in flight between the stages
+    pub fn from_args() -> std::io::Result<Self> {
+        let sources = opt_arg("reader").map_or(Ok(vec![Source::Pread]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        let compute = opt_arg("compute").map_or(Ok(Compute::default()), |c| c.parse())?;
+        let buffers = match opt_arg("buffers") {
+            None => None,
+            Some(b) => match b.parse::<usize>() {
+                Ok(b) if b > 0 => Some(b),
+                _ => {
+                    return Err(IOError::new(
+                        IOErrorKind::InvalidInput,
+                        format!("Wrong number of buffers '{}'", b).as_str(),
+                    ))
+                }
+            },
+        };
+        Ok(PipeConfig {
+            source: sources[0],
+            sources,
+            compute,
+            buffers,
+        })
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// How `par_copy` reads the source.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadEngine {
+    /// `pread` into the worker's buffer.
+    Pread,
+    /// `pread` from a file opened with `O_DIRECT`.
+    Direct,
+    /// Slices of a mapping of the source, no copy.
+    Mmap,
+    /// One io_uring read in flight per worker.
+    Uring,
+}
+
+option_enum!(ReadEngine, "reader", {
+    "pread" => Pread,
+    "direct" => Direct,
+    "mmap" => Mmap,
+    "uring" => Uring,
+});
+
+/// How `par_copy` writes the destination.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WriteEngine {
+    /// `pwrite` from the worker's buffer.
+    Pwrite,
+    /// `pwrite` to a file opened with `O_DIRECT`.
+    Direct,
+    /// The data is read straight into a mapping of the destination, or copied
+    /// into it from a mapped source; flushed once the workers end.
+    Mmap,
+    /// One io_uring write in flight per worker.
+    Uring,
+}
+
+option_enum!(WriteEngine, "writer", {
+    "pwrite" => Pwrite,
+    "direct" => Direct,
+    "mmap" => Mmap,
+    "uring" => Uring,
+});
+
+/// Options of `par_copy`.
+#[derive(Clone, Debug)]
+pub struct CopyConfig {
+    pub reader: ReadEngine,
+    pub writer: WriteEngine,
+    /// Reserve the blocks of the destination with `fallocate` before copying.
+    pub prealloc: bool,
+    /// Compare the destination with the source once copied.
+    pub verify: bool,
+}
+
+impl Default for CopyConfig {
+    fn default() -> Self {
+        CopyConfig {
+            reader: ReadEngine::Pread,
+            writer: WriteEngine::Pwrite,
+            prealloc: false,
+            verify: false,
+        }
+    }
+}
+
+impl CopyConfig {
+    /// Build from the command line options:
+    /// - `--reader=pread|direct|mmap|uring`: how the source is read
+    /// - `--writer=pwrite|direct|mmap|uring`: how the destination is written
+    /// - `--prealloc`: `fallocate` the destination
+    /// - `--verify`: compare the files after copying
+    pub fn from_args() -> std::io::Result<Self> {
+        let d = CopyConfig::default();
+        Ok(CopyConfig {
+            reader: opt_arg("reader").map_or(Ok(d.reader), |r| r.parse())?,
+            writer: opt_arg("writer").map_or(Ok(d.writer), |w| w.parse())?,
+            prealloc: opt_arg("prealloc").is_some(),
+            verify: opt_arg("verify").is_some(),
+        })
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// Socket between the sender and the receiver of `net_copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+    /// TCP over the loopback interface, `127.0.0.1`.
+    Tcp,
+    /// Unix domain stream socket.
+    Unix,
+}
+
+option_enum!(Transport, "transport", {
+    "tcp" => Tcp,
+    "unix" => Unix,
+});
+
+/// How the `net_copy` senders read the source and send it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SendEngine {
+    /// `pread` into the sender's buffer, then `write` to the socket.
+    Read,
+    /// Socket writes straight from a mapping of the source.
+    Mmap,
+    /// `sendfile` from the source to the socket, no user space copy.
+    Sendfile,
+    /// io_uring read of the file then write to the socket.
+    Uring,
+}
+
+option_enum!(SendEngine, "sender", {
+    "read" => Read,
+    "mmap" => Mmap,
+    "sendfile" => Sendfile,
+    "uring" => Uring,
+});
+
+/// How the `net_copy` receivers get the data and write the destination.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecvEngine {
+    /// `read` from the socket into the receiver's buffer, then `pwrite`.
+    Write,
+    /// Socket reads straight into a mapping of the destination, flushed once
+    /// the receivers end.
+    Mmap,
+    /// `splice` from the socket to the destination through a pipe.
+    Splice,
+    /// io_uring read of the socket then write to the file.
+    Uring,
+}
+
+option_enum!(RecvEngine, "receiver", {
+    "write" => Write,
+    "mmap" => Mmap,
+    "splice" => Splice,
+    "uring" => Uring,
+});
+
+/// Options of `net_copy`.
+#[derive(Clone, Debug)]
+pub struct NetConfig {
+    pub transport: Transport,
+    /// Sender method of the current run.
+    pub sender: SendEngine,
+    /// All the sender methods requested on the command line.
+    pub senders: Vec<SendEngine>,
+    /// Receiver method of the current run.
+    pub receiver: RecvEngine,
+    /// All the receiver methods requested on the command line, one run for
+    /// each sender and receiver pair.
+    pub receivers: Vec<RecvEngine>,
+}
+
+impl NetConfig {
+    /// Build from the command line options:
+    /// - `--transport=tcp|unix`: socket type
+    /// - `--sender=read|mmap|sendfile|uring[,...]`: sender methods
+    /// - `--receiver=write|mmap|splice|uring[,...]`: receiver methods
+    pub fn from_args() -> std::io::Result<Self> {
+        let transport = opt_arg("transport").map_or(Ok(Transport::Tcp), |t| t.parse())?;
+        let senders = opt_arg("sender").map_or(Ok(vec![SendEngine::Read]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        let receivers = opt_arg("receiver").map_or(Ok(vec![RecvEngine::Write]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        Ok(NetConfig {
+            transport,
+            sender: senders[0],
+            senders,
+            receiver: receivers[0],
+            receivers,
+        })
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// How the mmap engines map the file.
+#[derive(Clone, Debug, Default)]
+pub struct MmapConfig {
+    /// `madvise` advice applied to the whole mapping, in order.
+    pub madvise: Vec<i32>,
+    /// Map with `MAP_POPULATE`.
+    pub populate: bool,
+    /// Map writable files with `MAP_PRIVATE`: written pages are never written
+    /// back, a baseline for the cost of `MAP_SHARED` writeback.
+    pub private: bool,
+    /// When the writers flush the mapping.
+    pub flush: Flush,
+    /// Flush with `MS_ASYNC` instead of `MS_SYNC`.
+    pub flush_async: bool,
+}
+
+impl MmapConfig {
+    /// Build from the command line options:
+    /// - `--madvise=sequential,willneed,hugepage,populate-read`
+    /// - `--populate`: prefault the mapping with `MAP_POPULATE`
+    /// - `--map=shared|private`: mapping type of the writers
+    /// - `--flush=chunk|thread|end`: when the writers flush the mapping
+    /// - `--flush-async`: flush with `MS_ASYNC`
+    pub fn from_args() -> std::io::Result<Self> {
+        let mut cfg = MmapConfig::default();
+        if let Some(a) = opt_arg("madvise") {
+            cfg.madvise = parse_madvise(&a).ok_or_else(|| {
+                IOError::new(
+                    IOErrorKind::InvalidInput,
+                    format!(
+                        "Wrong madvise advice '{}', use sequential,willneed,hugepage,populate-read",
+                        a
+                    )
+                    .as_str(),
+                )
+            })?;
+        }
+        cfg.populate = opt_arg("populate").is_some();
+        if let Some(m) = opt_arg("map") {
+            cfg.private = match m.as_str() {
+                "shared" => false,
+                "private" => true,
+                _ => {
+                    return Err(IOError::new(
+                        IOErrorKind::InvalidInput,
+                        format!("Unknown mapping type '{}', use shared|private", m).as_str(),
+                    ))
+                }
+            };
+        }
+        if let Some(f) = opt_arg("flush") {
+            cfg.flush = f.parse()?;
+        }
+        cfg.flush_async = opt_arg("flush-async").is_some();
+        Ok(cfg)
+    }
+}
+
+//-----------------------------------------------------------------------------
+pub struct ParConfig {
+    /// CPUs the workers are pinned to, worker `i` gets `cpus[i % cpus.len()]`;
+    /// empty means no pinning.
+    pub cpus: Vec<usize>,
+    /// Placement of each worker's slice of the shared buffer.
+    pub numa: NumaPolicy,
+    /// Node layout, only read when a NUMA policy is selected.
+    pub nodes: Vec<NumaNode>,
+    /// Persistent threads the workers run on instead of spawning new ones.
+    pub pool: Option<ThreadPool>,
+    /// Requests each worker keeps in flight, used by the asynchronous engines.
+    pub queue_depth: usize,
+    /// Per-call `RWF_*` flags of the preadv2/pwritev2 engines.
+    pub rwf: i32,
+    /// Mapping options of the mmap engines.
+    pub mmap: MmapConfig,
+    /// Cap on the size of the data buffer, when the data does not fit the
+    /// workers reuse a ring of chunk buffers.
+    pub mem_budget: Option<u64>,
+    /// Hash of every chunk read or written, none when not set.
+    pub hash: Option<Hash>,
+    /// Options of the current run.
+    pub variant: Variant,
+    /// All the combinations requested on the command line, in report order.
+    pub variants: Vec<Variant>,
+}
+
+impl Default for ParConfig {
+    fn default() -> Self {
+        ParConfig {
+            cpus: Vec::new(),
+            numa: NumaPolicy::None,
+            nodes: Vec::new(),
+            pool: None,
+            queue_depth: 1,
+            rwf: 0,
+            mmap: MmapConfig::default(),
+            mem_budget: None,
+            hash: None,
+            variant: Variant::default(),
+            variants: vec![Variant::default()],
+        }
+    }
+}
+
+impl ParConfig {
+    /// Build from the command line options:
+    /// - `--cpus=<list>`: pin workers to a cpu list, e.g. `0-7,16-23`
+    /// - `--numa=none|first-touch|bind`: per-worker buffer placement
+    /// - `--pool`: run the workers on a thread pool reused across runs
+    /// - `--queue-depth=<n>`: requests in flight per worker, async engines only
+    /// - `--rwf=hipri,nowait,dsync,sync,append`: preadv2/pwritev2 flags, `hipri`
+    ///   also opens the file with `O_DIRECT`, `append` empties the files first
+    ///   and needs a single worker per file
+    /// - `--madvise=...`, `--populate`, `--map=...`, `--flush=...`,
+    ///   `--flush-async`: mmap options, see `MmapConfig`
+    /// - `--mem-budget=<size>`: cap on the data buffer, e.g. `1G`; queue-depth
+    ///   engines need `threads * queue-depth` chunks
+    /// - `--hash=crc32c|xxh3|sha256`: hash every chunk
+    /// - `--schedule=static|dynamic[,...]`: chunk distribution
+    /// - `--partition=contiguous|strided|random-permutation[,...]`: which
+    ///   chunks each worker gets
+    /// - `--files=shared|per-thread[,...]`: one file accessed by all the
+    ///   workers (N-to-1) or one file `<fname>.<i>` per worker (N-to-N), only
+    ///   combined with the static schedule and the contiguous partition
+    /// - `--mode=threads|processes[,...]`: run the workers as threads or as
+    ///   forked processes, the latter without `--pool`
+    ///
+    /// Lists run every method with each combination of the options.
+    pub fn from_args() -> std::io::Result<Self> {
+        let mut cfg = ParConfig::default();
+        if let Some(c) = opt_arg("cpus") {
+            cfg.cpus = parse_cpu_list(&c).ok_or_else(|| {
+                IOError::new(
+                    IOErrorKind::InvalidInput,
+                    format!("Wrong cpu list '{}'", c).as_str(),
+                )
+            })?;
+        }
+        if let Some(n) = opt_arg("numa") {
+            cfg.numa = n.parse()?;
+        }
+        if opt_arg("pool").is_some() {
+            cfg.pool = Some(ThreadPool::default());
+        }
+        if let Some(d) = opt_arg("queue-depth") {
+            cfg.queue_depth = match d.parse::<usize>() {
+                Ok(d) if d > 0 => d,
+                _ => {
+                    return Err(IOError::new(
+                        IOErrorKind::InvalidInput,
+                        format!("Wrong queue depth '{}'", d).as_str(),
+                    ))
+                }
+            };
+        }
+        if let Some(f) = opt_arg("rwf") {
+            cfg.rwf = parse_rwf(&f).ok_or_else(|| {
+                IOError::new(
+                    IOErrorKind::InvalidInput,
+                    format!(
+                        "Wrong RWF flags '{}', use hipri,nowait,dsync,sync,append",
+                        f
+                    )
+                    .as_str(),
+                )
+            })?;
+        }
+        cfg.mmap = MmapConfig::from_args()?;
+        cfg.mem_budget = mem_budget()?;
+        cfg.hash = hash()?;
+        let schedules = opt_arg("schedule").map_or(Ok(vec![Schedule::Static]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        let partitions = opt_arg("partition").map_or(Ok(vec![Partition::Contiguous]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        let files = opt_arg("files").map_or(Ok(vec![Files::Shared]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        let modes = opt_arg("mode").map_or(Ok(vec![Mode::Threads]), |l| {
+            l.split(',')
+                .map(|s| s.parse())
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        // forking while the pool threads run can deadlock the children
+        if cfg.pool.is_some() && modes.contains(&Mode::Processes) {
+            return Err(IOError::new(
+                IOErrorKind::InvalidInput,
+                "--pool cannot be combined with --mode=processes",
+            ));
+        }
+        cfg.variants = Vec::new();
+        for &schedule in &schedules {
+            for &partition in &partitions {
+                for &files in &files {
+                    // per-thread files are only split statically into blocks
+                    if files == Files::PerThread
+                        && (schedule != Schedule::Static || partition != Partition::Contiguous)
+                    {
+                        println!(
+                            "Skipping {}, {}, {}: per-thread files need a static, contiguous split",
+                            schedule, partition, files
+                        );
+                        continue;
+                    }
+                    for &mode in &modes {
+                        cfg.variants.push(Variant {
+                            schedule,
+                            partition,
+                            files,
+                            mode,
+                        });
+                    }
+                }
+            }
+        }
+        if cfg.variants.is_empty() {
+            return Err(IOError::new(
+                IOErrorKind::InvalidInput,
+                "--files=per-thread requires --schedule=static and --partition=contiguous",
+            ));
+        }
+        cfg.variant = cfg.variants[0];
+        if cfg.numa != NumaPolicy::None {
+            cfg.nodes = numa_nodes();
+        }
+        Ok(cfg)
+    }
+
+    /// Report label of method `name` run with the current hash and variant;
+    /// the variant is only shown when more than one was requested.
+    pub fn label(&self, name: &str) -> String {
+        let name = match self.hash {
+            Some(h) => format!("{}+{}", name, h),
+            None => name.to_owned(),
+        };
+        if self.variants.len() > 1 {
+            format!("{} [{}]", name, self.variant)
+        } else {
+            name
+        }
+    }
+
+    /// Hash settings to run each method with: without hashing, then with the
+    /// selected hash if any.
+    pub fn hashes(&self) -> Vec<Option<Hash>> {
+        match self.hash {
+            Some(h) => vec![None, Some(h)],
+            None => vec![None],
+        }
+    }
+
+    /// CPU worker `i` is pinned to, if any.
+    pub fn cpu(&self, i: u64) -> Option<usize> {
+        if self.cpus.is_empty() {
+            None
+        } else {
+            Some(self.cpus[i as usize % self.cpus.len()])
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// `--mem-budget=<size>` option, in bytes.
+pub fn mem_budget() -> std::io::Result<Option<u64>> {
+    opt_arg("mem-budget")
+        .map(|m| {
+            parse_size(&m).filter(|&m| m > 0).ok_or_else(|| {
+                IOError::new(
+                    IOErrorKind::InvalidInput,
+                    format!("Wrong memory budget '{}'", m).as_str(),
+                )
+            })
+        })
+        .transpose()
+}
+
+/// `--hash=<hash>` option.
+pub fn hash() -> std::io::Result<Option<Hash>> {
+    opt_arg("hash").map(|h| h.parse()).transpose()
+}
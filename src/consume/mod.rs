@@ -0,0 +1,185 @@
+//! Zero-copy reading: the engines hand borrowed slices of the data, pages of
+//! a mapping or a reused i/o buffer, to a consumer instead of copying the file
+//! into a buffer of the same size.
+use crate::config::{MmapConfig, ParConfig};
+use crate::digest::Digests;
+use crate::schedule::Chunks;
+use crate::utility::{map_file, pread_exact, MmapTiming, Movable};
+use crate::workers::{ParTiming, Workers};
+use aligned_vec::*;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Receives the offset of each chunk in the data set and its content, the
+/// slice is only valid for the duration of the call. Parallel engines give
+/// each worker its own clone.
+pub trait Consumer: FnMut(u64, &[u8]) -> std::io::Result<()> + Clone + Send + 'static {}
+
+impl<T> Consumer for T where T: FnMut(u64, &[u8]) -> std::io::Result<()> + Clone + Send + 'static {}
+
+//-----------------------------------------------------------------------------
+/// Consumer reading every byte: wrapping sum of the 64 bit words.
+pub fn checksum(_offset: u64, data: &[u8]) -> std::io::Result<()> {
+    let words = data.chunks_exact(8);
+    let tail = words
+        .remainder()
+        .iter()
+        .fold(0_u64, |s, &b| s.wrapping_add(b as u64));
+    let sum = words.fold(tail, |s, w| {
+        s.wrapping_add(u64::from_ne_bytes(w.try_into().unwrap()))
+    });
+    std::hint::black_box(sum);
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------
+/// Consumer with a synthetic cost: busy waits for the time taken to process
+/// the data at `mib_per_s` MiB/s.
+pub fn spin(mib_per_s: u64) -> impl Consumer {
+    move |_offset: u64, data: &[u8]| {
+        let cost = Duration::from_secs_f64(data.len() as f64 / (mib_per_s * 0x100000) as f64);
+        let t = Instant::now();
+        while t.elapsed() < cost {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------------------------------
+// one chunk sized buffer reused for all the reads
+pub fn seq_consume_read<C: Consumer>(
+    fname: &str,
+    chunk_size: u64,
+    digests: &Digests,
+    mut consumer: C,
+) -> std::io::Result<Duration> {
+    let fsize = std::fs::metadata(fname)?.len();
+    let mut file = std::fs::File::open(fname)?;
+    let mut buf: Vec<u8> =
+        page_aligned_vec(chunk_size as usize, chunk_size as usize, Some(0), false);
+    let mut r = 0_u64;
+    let t = Instant::now();
+    while r < fsize {
+        let len = chunk_size.min(fsize - r) as usize;
+        file.read_exact(&mut buf[..len])?;
+        digests.record(r, &buf[..len]);
+        consumer(r, &buf[..len])?;
+        r += len as u64;
+    }
+    Ok(t.elapsed())
+}
+
+//-----------------------------------------------------------------------------
+// chunks are slices of the mapping, `copy` is the time spent consuming
+pub fn seq_consume_mmap<C: Consumer>(
+    fname: &str,
+    chunk_size: u64,
+    cfg: &MmapConfig,
+    digests: &Digests,
+    mut consumer: C,
+) -> std::io::Result<MmapTiming> {
+    let fsize = std::fs::metadata(fname)?.len();
+    let file = std::fs::File::open(fname)?;
+    let (mmap, prefault) = map_file(&file, cfg)?;
+    let mut r = 0_u64;
+    let t = Instant::now();
+    while r < fsize {
+        let b = r as usize;
+        let e = (b + chunk_size as usize).min(fsize as usize);
+        digests.record(r, &mmap[b..e]);
+        consumer(r, &mmap[b..e])?;
+        r = e as u64;
+    }
+    Ok(MmapTiming {
+        prefault,
+        copy: t.elapsed(),
+        writeback: Duration::ZERO,
+    })
+}
+
+//-----------------------------------------------------------------------------
+// each worker reuses one chunk sized buffer, placed as selected by `--numa`
+pub fn par_consume_pread<C: Consumer>(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    size: u64,
+    cfg: &ParConfig,
+    consumer: C,
+) -> std::io::Result<ParTiming> {
+    let chunks = Chunks::new(size, chunk_size, num_threads, cfg.variant, size as usize)?;
+    let digests = Arc::new(Digests::new(cfg.hash, size, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    for i in 0..num_threads {
+        let chunks = chunks.clone();
+        let fname = chunks.file_name(fname, i);
+        let mut consumer = consumer.clone();
+        let digests = digests.clone();
+        workers.spawn(i, move |worker| {
+            let file = std::fs::File::open(&fname)?;
+            let fd = file.as_raw_fd();
+            let mut buf: Vec<u8> =
+                page_aligned_vec(chunk_size as usize, chunk_size as usize, None, false);
+            worker.place_mut(&mut buf)?;
+            worker.start();
+            for r in chunks.iter(i) {
+                let len = r.len();
+                pread_exact(fd, &mut buf[..len], chunks.offset(i, &r))?;
+                digests.record(r.start as u64, &buf[..len]);
+                consumer(r.start as u64, &buf[..len])?;
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// files are mapped, and prefaulted, once before the workers start as in
+// `par_read_mmap_all`, chunks are slices of the mappings
+pub fn par_consume_mmap<C: Consumer>(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    size: u64,
+    cfg: &ParConfig,
+    consumer: C,
+) -> std::io::Result<ParTiming> {
+    let chunks = Chunks::new(size, chunk_size, num_threads, cfg.variant, size as usize)?;
+    let digests = Arc::new(Digests::new(cfg.hash, size, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let mut maps = Vec::new();
+    let mut prefault = Duration::ZERO;
+    for f in 0..chunks.num_files() {
+        let file = std::fs::File::open(chunks.file_name(fname, f))?;
+        let (mmap, t) = map_file(&file, &cfg.mmap)?;
+        maps.push(mmap);
+        prefault += t;
+    }
+    for i in 0..num_threads {
+        let map = &maps[if maps.len() > 1 { i as usize } else { 0 }];
+        let src = Movable(map.as_ptr());
+        let chunks = chunks.clone();
+        let mut consumer = consumer.clone();
+        let digests = digests.clone();
+        workers.spawn(i, move |worker| {
+            worker.start();
+            for r in chunks.iter(i) {
+                let offset = chunks.offset(i, &r) as usize;
+                let data = unsafe { src.slice(offset..offset + r.len()) };
+                digests.record(r.start as u64, data);
+                consumer(r.start as u64, data)?;
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    timing.prefault = Some(prefault);
+    Ok(timing)
+}
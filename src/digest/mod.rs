@@ -0,0 +1,130 @@
+//! Per-chunk hashing stage of the engines: every chunk read or written is
+//! hashed into a table indexed by chunk, the digest of the whole data is the
+//! hash of the table, a one level Merkle tree. It is not the hash of the data
+//! as computed by `sha256sum` and the like, and it changes with the chunk size:
+//! digests can only be compared between runs with the same chunk size, which is
+//! printed with them.
+use crate::config::Hash;
+use crate::utility::{dump, fmt_size};
+use sha2::{Digest, Sha256};
+
+// bytes of the table entry of each chunk, the largest hash
+const SLOT: usize = 32;
+
+impl Hash {
+    /// Size of the hash in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Hash::Crc32c => 4,
+            Hash::Xxh3 => 8,
+            Hash::Sha256 => 32,
+        }
+    }
+    /// Hash `data` into `out`, of `size()` bytes.
+    pub fn write(self, data: &[u8], out: &mut [u8]) {
+        match self {
+            Hash::Crc32c => out.copy_from_slice(&crc32c::crc32c(data).to_be_bytes()),
+            Hash::Xxh3 => out.copy_from_slice(&xxhash_rust::xxh3::xxh3_64(data).to_be_bytes()),
+            Hash::Sha256 => out.copy_from_slice(&Sha256::digest(data)),
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// Hashes of the chunks, in memory shared with forked workers; does nothing
+/// when no hash is selected.
+pub struct Digests {
+    hash: Option<Hash>,
+    chunk_size: u64,
+    table: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for Digests {}
+unsafe impl Sync for Digests {}
+
+impl Digests {
+    /// Table for the chunks of `size` bytes of data.
+    pub fn new(hash: Option<Hash>, size: u64, chunk_size: u64) -> std::io::Result<Self> {
+        let len = match hash {
+            Some(_) => size.div_ceil(chunk_size) as usize * SLOT,
+            None => 0,
+        };
+        let table = if len == 0 {
+            std::ptr::null_mut()
+        } else {
+            let p = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            if p == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error());
+            }
+            p as *mut u8
+        };
+        Ok(Digests {
+            hash,
+            chunk_size,
+            table,
+            len,
+        })
+    }
+
+    /// Hash the chunks of `data`, starting with the chunk at `offset` in the
+    /// data; the last chunk may be shorter.
+    pub fn record(&self, offset: u64, data: &[u8]) {
+        let Some(h) = self.hash else {
+            return;
+        };
+        let first = (offset / self.chunk_size) as usize;
+        for (k, c) in data.chunks(self.chunk_size as usize).enumerate() {
+            let slot = unsafe {
+                std::slice::from_raw_parts_mut(self.table.add((first + k) * SLOT), h.size())
+            };
+            h.write(c, slot);
+        }
+    }
+
+    /// No-hash path of the readers: without a hash nothing looks at the data
+    /// read, `dump` it so that the reads cannot be optimized away.
+    pub fn dump_unhashed(&self, data: &[u8]) -> std::io::Result<()> {
+        match self.hash {
+            Some(_) => Ok(()),
+            None => dump(data),
+        }
+    }
+
+    /// Chunked digest of the whole data: hash of the chunk hashes in data
+    /// order, as `<hash>/<chunk size>:<hex digest>`.
+    pub fn chunked_digest(&self) -> Option<String> {
+        let h = self.hash?;
+        let table: &[u8] = if self.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.table, self.len) }
+        };
+        let hashes: Vec<u8> = table
+            .chunks(SLOT)
+            .flat_map(|s| &s[..h.size()])
+            .copied()
+            .collect();
+        let mut d = vec![0_u8; h.size()];
+        h.write(&hashes, &mut d);
+        let hex: String = d.iter().map(|b| format!("{:02x}", b)).collect();
+        Some(format!("{}/{}:{}", h, fmt_size(self.chunk_size), hex))
+    }
+}
+
+impl Drop for Digests {
+    fn drop(&mut self) {
+        if !self.table.is_null() {
+            unsafe { libc::munmap(self.table as *mut libc::c_void, self.len) };
+        }
+    }
+}
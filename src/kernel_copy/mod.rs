@@ -0,0 +1,271 @@
+//! File to file copy without going through user space: `copy_file_range`,
+//! `sendfile`, `splice` and reflinks, next to a user space `pread` + `pwrite`
+//! copy. Every copy ends with a sync of the destination, counted in its time.
+use crate::config::{CopyConfig, ParConfig};
+use crate::par_copy::{create_files, par_copy};
+use crate::schedule::{data_size, Chunks};
+use crate::utility::{
+    copy_file_range_all, pread_exact, pwrite_all, sendfile_all, splice_all, unsupported, Pipe,
+};
+use crate::workers::{ParTiming, Workers};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+// not in all the versions of the libc crate: _IOW(0x94, 9, int) and
+// _IOW(0x94, 13, struct file_clone_range)
+const FICLONE: libc::c_ulong = 0x40049409;
+const FICLONERANGE: libc::c_ulong = 0x4020940d;
+
+#[repr(C)]
+struct FileCloneRange {
+    src_fd: i64,
+    src_offset: u64,
+    src_length: u64,
+    dest_offset: u64,
+}
+
+// source opened for reading, destination created or truncated
+fn open_files(src: &str, dst: &str) -> std::io::Result<(std::fs::File, std::fs::File, u64)> {
+    let src = std::fs::File::open(src)?;
+    let dst = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dst)?;
+    let size = src.metadata()?.len();
+    Ok((src, dst, size))
+}
+
+// share the extents of `len` bytes from `offset` of `src` with `dst`
+fn clone_range(src: RawFd, dst: RawFd, offset: u64, len: u64) -> std::io::Result<()> {
+    let range = FileCloneRange {
+        src_fd: src as i64,
+        src_offset: offset,
+        src_length: len,
+        dest_offset: offset,
+    };
+    if unsafe { libc::ioctl(dst, FICLONERANGE as _, &range) } != 0 {
+        return Err(unsupported("FICLONERANGE", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------
+// one chunk sized buffer reused for all the chunks
+pub fn seq_copy_pread(src: &str, dst: &str, chunk_size: u64) -> std::io::Result<Duration> {
+    let (src, dst, size) = open_files(src, dst)?;
+    let mut buf = vec![0_u8; chunk_size as usize];
+    let t = Instant::now();
+    let mut r = 0_u64;
+    while r < size {
+        let len = chunk_size.min(size - r) as usize;
+        pread_exact(src.as_raw_fd(), &mut buf[..len], r)?;
+        pwrite_all(dst.as_raw_fd(), &buf[..len], r)?;
+        r += len as u64;
+    }
+    dst.sync_all()?;
+    Ok(t.elapsed())
+}
+
+//-----------------------------------------------------------------------------
+// one call per chunk
+pub fn seq_copy_file_range(src: &str, dst: &str, chunk_size: u64) -> std::io::Result<Duration> {
+    let (src, dst, size) = open_files(src, dst)?;
+    let t = Instant::now();
+    let mut r = 0_u64;
+    while r < size {
+        let len = chunk_size.min(size - r) as usize;
+        copy_file_range_all(src.as_raw_fd(), dst.as_raw_fd(), r, len)?;
+        r += len as u64;
+    }
+    dst.sync_all()?;
+    Ok(t.elapsed())
+}
+
+//-----------------------------------------------------------------------------
+// the destination is written at its file position, sequentially
+pub fn seq_sendfile(src: &str, dst: &str, chunk_size: u64) -> std::io::Result<Duration> {
+    let (src, dst, size) = open_files(src, dst)?;
+    let t = Instant::now();
+    let mut r = 0_u64;
+    while r < size {
+        let len = chunk_size.min(size - r) as usize;
+        sendfile_all(dst.as_raw_fd(), src.as_raw_fd(), r, len)?;
+        r += len as u64;
+    }
+    dst.sync_all()?;
+    Ok(t.elapsed())
+}
+
+//-----------------------------------------------------------------------------
+// through a pipe of one chunk, or the largest size allowed
+pub fn seq_splice(src: &str, dst: &str, chunk_size: u64) -> std::io::Result<Duration> {
+    let (src, dst, size) = open_files(src, dst)?;
+    let pipe = Pipe::new(chunk_size as usize)?;
+    let t = Instant::now();
+    let mut r = 0_u64;
+    while r < size {
+        let len = chunk_size.min(size - r) as usize;
+        splice_all(
+            src.as_raw_fd(),
+            Some(r),
+            dst.as_raw_fd(),
+            Some(r),
+            len,
+            &pipe,
+        )?;
+        r += len as u64;
+    }
+    dst.sync_all()?;
+    Ok(t.elapsed())
+}
+
+//-----------------------------------------------------------------------------
+// reflink of the whole file, the chunk size is not used; only file systems
+// with shared extents support it, e.g. btrfs or xfs
+pub fn seq_ficlone(src: &str, dst: &str, _chunk_size: u64) -> std::io::Result<Duration> {
+    let (src, dst, _) = open_files(src, dst)?;
+    let t = Instant::now();
+    if unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } != 0 {
+        return Err(unsupported("FICLONE", std::io::Error::last_os_error()));
+    }
+    dst.sync_all()?;
+    Ok(t.elapsed())
+}
+
+//-----------------------------------------------------------------------------
+// user space copy through each worker's buffer, see `par_copy`
+pub fn par_copy_pread(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    Ok(par_copy(
+        src,
+        dst,
+        chunk_size,
+        num_threads,
+        cfg,
+        &CopyConfig::default(),
+    )?
+    .copy)
+}
+
+//-----------------------------------------------------------------------------
+// the workers copy their chunks with one call of `f` per chunk, with the
+// worker's `state` built from the chunk size before the worker starts
+fn par_kernel_copy<S, F>(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+    state: fn(u64) -> std::io::Result<S>,
+    f: F,
+) -> std::io::Result<ParTiming>
+where
+    S: 'static,
+    F: Fn(&S, RawFd, RawFd, u64, usize) -> std::io::Result<()> + Copy + Send + 'static,
+{
+    let fsize = data_size(src, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, fsize as usize)?;
+    create_files(dst, &chunks, false)?;
+    let mut workers = Workers::new(cfg, num_threads)?;
+    for i in 0..num_threads {
+        let chunks = chunks.clone();
+        let src_name = chunks.file_name(src, i);
+        let dst_name = chunks.file_name(dst, i);
+        workers.spawn(i, move |worker| {
+            let src = std::fs::File::open(&src_name)?;
+            let dst = std::fs::OpenOptions::new().write(true).open(&dst_name)?;
+            let s = state(chunk_size)?;
+            worker.start();
+            for r in chunks.iter(i) {
+                f(
+                    &s,
+                    src.as_raw_fd(),
+                    dst.as_raw_fd(),
+                    chunks.offset(i, &r),
+                    r.len(),
+                )?;
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    // synced as in `par_copy`
+    let t = Instant::now();
+    for f in 0..chunks.num_files() {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(chunks.file_name(dst, f))?
+            .sync_all()?;
+    }
+    let t = t.elapsed();
+    timing.after += t;
+    timing.writeback = Some(t);
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+pub fn par_copy_file_range(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    par_kernel_copy(
+        src,
+        dst,
+        chunk_size,
+        num_threads,
+        cfg,
+        |_| Ok(()),
+        |_, s, d, o, len| copy_file_range_all(s, d, o, len),
+    )
+}
+
+//-----------------------------------------------------------------------------
+// one pipe per worker
+pub fn par_splice(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let pipe = |c: u64| Pipe::new(c as usize);
+    par_kernel_copy(
+        src,
+        dst,
+        chunk_size,
+        num_threads,
+        cfg,
+        pipe,
+        |p, s, d, o, len| splice_all(s, Some(o), d, Some(o), len, p),
+    )
+}
+
+//-----------------------------------------------------------------------------
+// reflink of each chunk, the chunk size must be a multiple of the file system
+// block size
+pub fn par_ficlonerange(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    par_kernel_copy(
+        src,
+        dst,
+        chunk_size,
+        num_threads,
+        cfg,
+        |_| Ok(()),
+        |_, s, d, o, len| clone_range(s, d, o, len as u64),
+    )
+}
@@ -1,6 +1,20 @@
+pub mod affinity;
+#[cfg(target_os = "linux")]
+pub mod aio;
+pub mod config;
+pub mod consume;
+pub mod digest;
+pub mod kernel_copy;
+pub mod net_copy;
+pub mod par_copy;
 pub mod par_read;
 pub mod par_write;
+pub mod pipeline;
+pub mod pool;
+pub mod posix_aio;
 pub mod read;
+pub mod schedule;
 pub mod utility;
 pub mod vec_io;
+pub mod workers;
 pub mod write;
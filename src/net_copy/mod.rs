@@ -0,0 +1,335 @@
+//! File to file transfer through local sockets: sender workers read the source
+//! and send it, receiver workers write what they receive to the destination.
+//! Each stream is one connection between a sender and a receiver, carrying a
+//! contiguous range of the file.
+use crate::config::{NetConfig, ParConfig, RecvEngine, SendEngine, Transport};
+use crate::par_copy::Uring;
+use crate::utility::{
+    file_size, flush_range, map_file, map_file_mut, pread_exact, pwrite_all, sendfile_all,
+    splice_all, Movable, MovableMut, Pipe,
+};
+use crate::workers::{Mode, ParTiming, ThreadTiming, Workers};
+use aligned_vec::*;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::OwnedFd;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+//-----------------------------------------------------------------------------
+/// Timings of one transfer.
+#[derive(Clone, Debug)]
+pub struct NetTiming {
+    /// All the workers, senders first; the elapsed time is end to end.
+    pub timing: ParTiming,
+    /// First sender starting to last sender done.
+    pub send: Duration,
+    /// First receiver starting to last receiver done, including the writeback
+    /// of the destination.
+    pub recv: Duration,
+}
+
+// first start to last end of some of the workers
+fn span(threads: &[ThreadTiming]) -> Duration {
+    let b = threads.iter().map(|t| t.start).min().unwrap_or_default();
+    let e = threads.iter().map(|t| t.end).max().unwrap_or_default();
+    e - b
+}
+
+/// Print end to end throughput, then the throughput seen by each side.
+pub fn print_net_timing(name: &str, size_gib: f64, timing: &NetTiming) {
+    let extra: String = timing
+        .timing
+        .prefault
+        .map(|p| format!(", prefault: {:.3} s", p.as_secs_f64()))
+        .into_iter()
+        .chain(
+            timing
+                .timing
+                .writeback
+                .map(|w| format!(", writeback: {:.3} s", w.as_secs_f64())),
+        )
+        .collect();
+    println!(
+        "{:<32} {:.2} GiB/s, aggregate: {:.3} s, send: {:.2} GiB/s, receive: {:.2} GiB/s{}",
+        format!("{}:", name),
+        size_gib / timing.timing.elapsed().as_secs_f64(),
+        timing.timing.elapsed().as_secs_f64(),
+        size_gib / timing.send.as_secs_f64(),
+        size_gib / timing.recv.as_secs_f64(),
+        extra
+    );
+}
+
+//-----------------------------------------------------------------------------
+// time a receiver waits for its sender to connect, a sender failing before
+// connecting would otherwise leave a receiver blocked in `accept`
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// listening socket, non-blocking so that `accept` can time out; connections
+// are turned into files to be used with the file descriptor based calls
+enum Listener {
+    Tcp(TcpListener, SocketAddr),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    // TCP on an ephemeral port, unix socket in the temporary directory
+    fn new(transport: Transport) -> std::io::Result<Self> {
+        match transport {
+            Transport::Tcp => {
+                let l = TcpListener::bind("127.0.0.1:0")?;
+                l.set_nonblocking(true)?;
+                let addr = l.local_addr()?;
+                Ok(Listener::Tcp(l, addr))
+            }
+            Transport::Unix => {
+                let path =
+                    std::env::temp_dir().join(format!("iobench-net-{}.sock", std::process::id()));
+                let _ = std::fs::remove_file(&path);
+                let l = UnixListener::bind(&path)?;
+                l.set_nonblocking(true)?;
+                Ok(Listener::Unix(l, path))
+            }
+        }
+    }
+    fn connect(&self) -> std::io::Result<File> {
+        let fd: OwnedFd = match self {
+            Listener::Tcp(_, addr) => {
+                let s = TcpStream::connect(addr)?;
+                s.set_nodelay(true)?;
+                s.into()
+            }
+            Listener::Unix(_, path) => UnixStream::connect(path)?.into(),
+        };
+        Ok(File::from(fd))
+    }
+    fn accept(&self) -> std::io::Result<File> {
+        let t = Instant::now();
+        loop {
+            let fd: std::io::Result<OwnedFd> = match self {
+                Listener::Tcp(l, _) => l.accept().and_then(|(s, _)| {
+                    s.set_nonblocking(false)?;
+                    Ok(s.into())
+                }),
+                Listener::Unix(l, _) => l.accept().and_then(|(s, _)| {
+                    s.set_nonblocking(false)?;
+                    Ok(s.into())
+                }),
+            };
+            match fd {
+                Ok(fd) => return Ok(File::from(fd)),
+                Err(e) if e.kind() != IOErrorKind::WouldBlock => return Err(e),
+                Err(_) if t.elapsed() >= ACCEPT_TIMEOUT => {
+                    return Err(IOError::new(
+                        IOErrorKind::TimedOut,
+                        format!("No sender connected within {} s", ACCEPT_TIMEOUT.as_secs())
+                            .as_str(),
+                    ))
+                }
+                Err(_) => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// Transfer `src` to `dst` over `num_streams` connections of `ncfg.transport`,
+/// the destination is created or overwritten. Each sender sends the offset
+/// and length of its range before the data, in chunks of `chunk_size`, so
+/// that receivers can accept the connections in any order.
+/// Workers are threads, `num_streams` senders then `num_streams` receivers.
+pub fn net_copy(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_streams: u64,
+    cfg: &ParConfig,
+    ncfg: &NetConfig,
+) -> std::io::Result<NetTiming> {
+    let (sender, receiver) = (ncfg.sender, ncfg.receiver);
+    if cfg.variant.mode == Mode::Processes {
+        return Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            "The net_copy workers can only run as threads",
+        ));
+    }
+    if num_streams == 0 || chunk_size == 0 {
+        return Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            "Stream count and chunk size must be greater than zero",
+        ));
+    }
+    // fail before any worker blocks waiting for its peer
+    if sender == SendEngine::Uring || receiver == RecvEngine::Uring {
+        Uring::new()?;
+    }
+    let fsize = file_size(src)?;
+    let dst_file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dst)?;
+    dst_file.set_len(fsize)?;
+    // whole chunks per stream, the last streams may get less or nothing
+    let stream_len = fsize.div_ceil(chunk_size).div_ceil(num_streams) * chunk_size;
+    // files mapped once before the workers start, empty files are not mapped
+    let mut prefault = Duration::ZERO;
+    let src_map = if sender == SendEngine::Mmap && fsize > 0 {
+        let (m, t) = map_file(&File::open(src)?, &cfg.mmap)?;
+        prefault += t;
+        Some(m)
+    } else {
+        None
+    };
+    let mut dst_map = if receiver == RecvEngine::Mmap && fsize > 0 {
+        let (m, t) = map_file_mut(&dst_file, fsize as usize, &cfg.mmap)?;
+        prefault += t;
+        Some(m)
+    } else {
+        None
+    };
+    let listener = Arc::new(Listener::new(ncfg.transport)?);
+    let mut workers = Workers::new(cfg, 2 * num_streams)?;
+    for i in 0..num_streams {
+        let listener = listener.clone();
+        let src = src.to_string();
+        let smap = Movable(src_map.as_ref().map_or(std::ptr::null(), |m| m.as_ptr()));
+        let begin = (i * stream_len).min(fsize);
+        let end = ((i + 1) * stream_len).min(fsize);
+        workers.spawn(i, move |worker| {
+            // connect first: the receivers block in `accept` until then
+            let mut sock = listener.connect()?;
+            let file = File::open(&src)?;
+            let mut header = [0_u8; 16];
+            header[..8].copy_from_slice(&begin.to_le_bytes());
+            header[8..].copy_from_slice(&(end - begin).to_le_bytes());
+            sock.write_all(&header)?;
+            let mut ring = if sender == SendEngine::Uring {
+                Some(Uring::new()?)
+            } else {
+                None
+            };
+            let mut buf: Vec<u8> =
+                page_aligned_vec(chunk_size as usize, chunk_size as usize, None, false);
+            worker.place_mut(&mut buf)?;
+            let (fd, sfd) = (file.as_raw_fd(), sock.as_raw_fd());
+            worker.start();
+            let mut offset = begin;
+            while offset < end {
+                let len = chunk_size.min(end - offset) as usize;
+                let o = offset as usize;
+                match sender {
+                    SendEngine::Read => {
+                        pread_exact(fd, &mut buf[..len], offset)?;
+                        sock.write_all(&buf[..len])?;
+                    }
+                    SendEngine::Mmap => sock.write_all(unsafe { smap.slice(o..o + len) })?,
+                    SendEngine::Sendfile => sendfile_all(sfd, fd, offset, len)?,
+                    SendEngine::Uring => {
+                        let ring = ring.as_mut().unwrap();
+                        ring.read(fd, &mut buf[..len], Some(offset))?;
+                        ring.write(sfd, &buf[..len], None)?;
+                    }
+                }
+                offset += len as u64;
+            }
+            Ok(())
+        });
+    }
+    for j in 0..num_streams {
+        let listener = listener.clone();
+        let dst = dst.to_string();
+        let dmap = MovableMut(
+            dst_map
+                .as_mut()
+                .map_or(std::ptr::null_mut(), |m| m.as_mut_ptr()),
+        );
+        workers.spawn(num_streams + j, move |worker| {
+            let mut sock = listener.accept()?;
+            let file = std::fs::OpenOptions::new().write(true).open(&dst)?;
+            let mut header = [0_u8; 16];
+            sock.read_exact(&mut header)?;
+            let begin = u64::from_le_bytes(header[..8].try_into().unwrap());
+            let end = begin + u64::from_le_bytes(header[8..].try_into().unwrap());
+            if end > fsize {
+                return Err(IOError::new(
+                    IOErrorKind::InvalidData,
+                    format!("Received range {}..{} past the end of the file", begin, end).as_str(),
+                ));
+            }
+            let pipe = if receiver == RecvEngine::Splice {
+                Some(Pipe::new(chunk_size as usize)?)
+            } else {
+                None
+            };
+            let mut ring = if receiver == RecvEngine::Uring {
+                Some(Uring::new()?)
+            } else {
+                None
+            };
+            let mut buf: Vec<u8> =
+                page_aligned_vec(chunk_size as usize, chunk_size as usize, None, false);
+            worker.place_mut(&mut buf)?;
+            let (fd, sfd) = (file.as_raw_fd(), sock.as_raw_fd());
+            worker.start();
+            let mut offset = begin;
+            while offset < end {
+                let len = chunk_size.min(end - offset) as usize;
+                let o = offset as usize;
+                match receiver {
+                    RecvEngine::Write => {
+                        sock.read_exact(&mut buf[..len])?;
+                        pwrite_all(fd, &buf[..len], offset)?;
+                    }
+                    RecvEngine::Mmap => sock.read_exact(unsafe { dmap.slice_mut(o..o + len) })?,
+                    RecvEngine::Splice => {
+                        splice_all(sfd, None, fd, Some(offset), len, pipe.as_ref().unwrap())?
+                    }
+                    RecvEngine::Uring => {
+                        let ring = ring.as_mut().unwrap();
+                        ring.read(sfd, &mut buf[..len], None)?;
+                        ring.write(fd, &buf[..len], Some(offset))?;
+                    }
+                }
+                offset += len as u64;
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    if src_map.is_some() || dst_map.is_some() {
+        timing.prefault = Some(prefault);
+    }
+    let n = num_streams as usize;
+    let send = span(&timing.threads[..n]);
+    let mut recv = span(&timing.threads[n..]);
+    // the received data is on disk, whatever the receiver engine
+    let t = match &dst_map {
+        Some(m) => flush_range(m, 0, m.len(), &cfg.mmap)?,
+        None => {
+            let t = Instant::now();
+            dst_file.sync_data()?;
+            t.elapsed()
+        }
+    };
+    timing.after += t;
+    timing.writeback = Some(t);
+    recv += t;
+    Ok(NetTiming { timing, send, recv })
+}
@@ -0,0 +1,391 @@
+//! Parallel file copy: the workers get the chunks of the source as selected by
+//! the variant, read each one with the `CopyConfig` reader and write it at the
+//! same offset of the destination with the writer.
+use crate::config::{CopyConfig, ParConfig, ReadEngine, WriteEngine};
+use crate::digest::Digests;
+use crate::schedule::{data_size, Chunks};
+use crate::utility::{
+    check_dio_align, flush_range, map_file, map_file_mut, pread_exact, pread_split, pwrite_all,
+    pwrite_split, Movable, MovableMut,
+};
+use crate::workers::{ParTiming, Workers};
+use aligned_vec::*;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+//-----------------------------------------------------------------------------
+/// Timings of one copy.
+#[derive(Clone, Debug)]
+pub struct CopyTiming {
+    pub copy: ParTiming,
+    /// Comparison of the two files, with `--verify`.
+    pub verify: Option<ParTiming>,
+}
+
+//-----------------------------------------------------------------------------
+/// io_uring with one request in flight, the caller waits for each read or
+/// write. Offsets are `None` for sockets and pipes.
+#[cfg(all(
+    any(feature = "par_copy_uring", feature = "net_uring"),
+    target_os = "linux"
+))]
+pub struct Uring(iou::IoUring);
+
+#[cfg(all(
+    any(feature = "par_copy_uring", feature = "net_uring"),
+    target_os = "linux"
+))]
+impl Uring {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Uring(iou::IoUring::new(1)?))
+    }
+    // short transfers are resubmitted for the rest of the buffer
+    fn transfer(
+        &mut self,
+        fd: RawFd,
+        buf: &mut [u8],
+        offset: Option<u64>,
+        write: bool,
+    ) -> std::io::Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            // non seekable files only accept offset 0
+            let off = offset.map_or(0, |o| o + done as u64);
+            let n = unsafe {
+                let mut sq = self.0.sq();
+                let mut sqe = sq.prepare_sqe().ok_or(IOError::other(
+                    "Failed to prepare io_uring submission queue",
+                ))?;
+                if write {
+                    sqe.prep_write(fd, &buf[done..], off);
+                } else {
+                    sqe.prep_read(fd, &mut buf[done..], off);
+                }
+                self.0.sq().submit()?;
+                let mut cq = self.0.cq();
+                let cqe = cq.wait_for_cqe()?;
+                cqe.result()? as usize
+            };
+            if n == 0 {
+                return Err(IOError::new(
+                    IOErrorKind::UnexpectedEof,
+                    format!("io_uring transferred 0 bytes at offset {}", off).as_str(),
+                ));
+            }
+            done += n;
+        }
+        Ok(())
+    }
+    pub fn read(&mut self, fd: RawFd, buf: &mut [u8], offset: Option<u64>) -> std::io::Result<()> {
+        self.transfer(fd, buf, offset, false)
+    }
+    pub fn write(&mut self, fd: RawFd, buf: &[u8], offset: Option<u64>) -> std::io::Result<()> {
+        // the buffer is only read by the kernel
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf.as_ptr() as *mut u8, buf.len()) };
+        self.transfer(fd, buf, offset, true)
+    }
+}
+
+#[cfg(not(all(
+    any(feature = "par_copy_uring", feature = "net_uring"),
+    target_os = "linux"
+)))]
+pub struct Uring;
+
+#[cfg(not(all(
+    any(feature = "par_copy_uring", feature = "net_uring"),
+    target_os = "linux"
+)))]
+impl Uring {
+    pub fn new() -> std::io::Result<Self> {
+        Err(IOError::new(
+            IOErrorKind::Unsupported,
+            "The io_uring engines require the par_copy_uring or net_uring feature",
+        ))
+    }
+    pub fn read(&mut self, _: RawFd, _: &mut [u8], _: Option<u64>) -> std::io::Result<()> {
+        unreachable!()
+    }
+    pub fn write(&mut self, _: RawFd, _: &[u8], _: Option<u64>) -> std::io::Result<()> {
+        unreachable!()
+    }
+}
+
+// open `path`, with `O_DIRECT` when `direct`
+fn open(path: &str, write: bool, direct: bool) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(write)
+        .custom_flags(if direct { libc::O_DIRECT } else { 0 })
+        .open(path)
+}
+
+//-----------------------------------------------------------------------------
+/// Create the destination files with the size of the source ones, stale data
+/// of a longer file is dropped; `prealloc` also reserves the blocks.
+pub fn create_files(dst: &str, chunks: &Chunks, prealloc: bool) -> std::io::Result<()> {
+    for f in 0..chunks.num_files() {
+        let name = chunks.file_name(dst, f);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&name)?;
+        let len = chunks.file_size(f);
+        if prealloc && len > 0 {
+            let ret = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) };
+            if ret != 0 {
+                let e = std::io::Error::last_os_error();
+                return Err(IOError::new(
+                    e.kind(),
+                    format!("Failed to preallocate '{}': {}", name, e).as_str(),
+                ));
+            }
+        }
+        file.set_len(len)?;
+    }
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------
+/// Copy `src` to `dst`, the destination is created or overwritten. With a
+/// variant using one file per worker each worker copies its own file. Chunks
+/// are hashed as they are read when `cfg.hash` is set. The destination files
+/// are synced once the workers end, the time taken is the `writeback`.
+pub fn par_copy(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+    ccfg: &CopyConfig,
+) -> std::io::Result<CopyTiming> {
+    let fsize = data_size(src, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, fsize as usize)?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let (reader, writer) = (ccfg.reader, ccfg.writer);
+    create_files(dst, &chunks, ccfg.prealloc)?;
+    // direct i/o transfers the unaligned tail of each file through a buffered
+    // descriptor
+    let src_align = if reader == ReadEngine::Direct {
+        check_dio_align(&chunks.file_name(src, 0), None, chunk_size, None)?.offset
+    } else {
+        1
+    };
+    let dst_align = if writer == WriteEngine::Direct {
+        check_dio_align(&chunks.file_name(dst, 0), None, chunk_size, None)?.offset
+    } else {
+        1
+    };
+    // files mapped once before the workers start, as in `par_read_mmap_all`
+    let mut src_maps = Vec::new();
+    let mut prefault = std::time::Duration::ZERO;
+    if reader == ReadEngine::Mmap {
+        for f in 0..chunks.num_files() {
+            let file = std::fs::File::open(chunks.file_name(src, f))?;
+            let (mmap, t) = map_file(&file, &cfg.mmap)?;
+            src_maps.push(mmap);
+            prefault += t;
+        }
+    }
+    let mut dst_maps = Vec::new();
+    if writer == WriteEngine::Mmap {
+        for f in 0..chunks.num_files() {
+            // empty files are not mapped
+            let len = chunks.file_size(f) as usize;
+            if len == 0 {
+                dst_maps.push(None);
+                continue;
+            }
+            let file = open(&chunks.file_name(dst, f), true, false)?;
+            let (mmap, t) = map_file_mut(&file, len, &cfg.mmap)?;
+            dst_maps.push(Some(mmap));
+            prefault += t;
+        }
+    }
+    let mut workers = Workers::new(cfg, num_threads)?;
+    for i in 0..num_threads {
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let src_name = chunks.file_name(src, i);
+        let dst_name = chunks.file_name(dst, i);
+        let f = if chunks.num_files() > 1 {
+            i as usize
+        } else {
+            0
+        };
+        let smap = Movable(src_maps.get(f).map_or(std::ptr::null(), |m| m.as_ptr()));
+        let dmap = MovableMut(
+            dst_maps
+                .get_mut(f)
+                .and_then(|m| m.as_mut())
+                .map_or(std::ptr::null_mut(), |m| m.as_mut_ptr()),
+        );
+        workers.spawn(i, move |worker| {
+            let src_file = open(&src_name, false, reader == ReadEngine::Direct)?;
+            let src_tail = std::fs::File::open(&src_name)?;
+            let dst_file = open(&dst_name, true, writer == WriteEngine::Direct)?;
+            let dst_tail = open(&dst_name, true, false)?;
+            let (sfd, stfd) = (src_file.as_raw_fd(), src_tail.as_raw_fd());
+            let (dfd, dtfd) = (dst_file.as_raw_fd(), dst_tail.as_raw_fd());
+            let src_direct_end = chunks.file_size(i) / src_align * src_align;
+            let dst_direct_end = chunks.file_size(i) / dst_align * dst_align;
+            let mut ring = if reader == ReadEngine::Uring || writer == WriteEngine::Uring {
+                Some(Uring::new()?)
+            } else {
+                None
+            };
+            let mut buf: Vec<u8> =
+                page_aligned_vec(chunk_size as usize, chunk_size as usize, None, false);
+            worker.place_mut(&mut buf)?;
+            worker.start();
+            for r in chunks.iter(i) {
+                let len = r.len();
+                let offset = chunks.offset(i, &r);
+                let o = offset as usize;
+                let data: &[u8] = if reader == ReadEngine::Mmap {
+                    unsafe { smap.slice(o..o + len) }
+                } else {
+                    // with a mapped destination the data is read in place
+                    let b = if dmap.get().is_some() {
+                        unsafe { dmap.slice_mut(o..o + len) }
+                    } else {
+                        &mut buf[..len]
+                    };
+                    match reader {
+                        ReadEngine::Pread => pread_exact(sfd, b, offset)?,
+                        ReadEngine::Direct => pread_split(sfd, stfd, b, offset, src_direct_end)?,
+                        ReadEngine::Uring => ring.as_mut().unwrap().read(sfd, b, Some(offset))?,
+                        ReadEngine::Mmap => unreachable!(),
+                    }
+                    b
+                };
+                digests.record(r.start as u64, data);
+                match writer {
+                    WriteEngine::Pwrite => pwrite_all(dfd, data, offset)?,
+                    WriteEngine::Direct => pwrite_split(dfd, dtfd, data, offset, dst_direct_end)?,
+                    WriteEngine::Mmap => {
+                        if reader == ReadEngine::Mmap {
+                            unsafe { dmap.slice_mut(o..o + len) }.copy_from_slice(data);
+                        }
+                    }
+                    WriteEngine::Uring => ring.as_mut().unwrap().write(dfd, data, Some(offset))?,
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    if !src_maps.is_empty() || !dst_maps.is_empty() {
+        timing.prefault = Some(prefault);
+    }
+    // the copy is only complete once durable: every writer pays for its
+    // writeback, mapped destinations are flushed first
+    let mut t = std::time::Duration::ZERO;
+    for m in dst_maps.iter().flatten() {
+        t += flush_range(m, 0, m.len(), &cfg.mmap)?;
+    }
+    let sync = std::time::Instant::now();
+    for f in 0..chunks.num_files() {
+        open(&chunks.file_name(dst, f), true, false)?.sync_all()?;
+    }
+    t += sync.elapsed();
+    timing.after += t;
+    timing.writeback = Some(t);
+    let verify = if ccfg.verify {
+        Some(par_verify(src, dst, chunk_size, num_threads, cfg)?)
+    } else {
+        None
+    };
+    Ok(CopyTiming {
+        copy: timing,
+        verify,
+    })
+}
+
+//-----------------------------------------------------------------------------
+/// Compare `dst` with `src` chunk by chunk, fails at the first difference.
+pub fn par_verify(
+    src: &str,
+    dst: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(src, cfg.variant.files, num_threads, chunk_size)?;
+    if data_size(dst, cfg.variant.files, num_threads, chunk_size)? != fsize {
+        return Err(IOError::new(
+            IOErrorKind::InvalidData,
+            format!("'{}' and '{}' differ in size", src, dst).as_str(),
+        ));
+    }
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, fsize as usize)?;
+    let mut workers = Workers::new(cfg, num_threads)?;
+    for i in 0..num_threads {
+        let chunks = chunks.clone();
+        let src_name = chunks.file_name(src, i);
+        let dst_name = chunks.file_name(dst, i);
+        workers.spawn(i, move |worker| {
+            let src_file = std::fs::File::open(&src_name)?;
+            let dst_file = std::fs::File::open(&dst_name)?;
+            let mut a = vec![0_u8; chunk_size as usize];
+            let mut b = vec![0_u8; chunk_size as usize];
+            worker.start();
+            for r in chunks.iter(i) {
+                let len = r.len();
+                let offset = chunks.offset(i, &r);
+                pread_exact(src_file.as_raw_fd(), &mut a[..len], offset)?;
+                pread_exact(dst_file.as_raw_fd(), &mut b[..len], offset)?;
+                if a[..len] != b[..len] {
+                    let k = (0..len).find(|&k| a[k] != b[k]).unwrap_or(0);
+                    return Err(IOError::new(
+                        IOErrorKind::InvalidData,
+                        format!(
+                            "'{}' differs from '{}' at offset {}",
+                            dst_name,
+                            src_name,
+                            offset + k as u64
+                        )
+                        .as_str(),
+                    ));
+                }
+            }
+            Ok(())
+        });
+    }
+    workers.join()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_unaligned_tail() {
+        let dir = std::env::temp_dir();
+        let src = dir.join(format!("iobench-copy-src-{}", std::process::id()));
+        let dst = dir.join(format!("iobench-copy-dst-{}", std::process::id()));
+        let (src, dst) = (src.to_str().unwrap(), dst.to_str().unwrap());
+        // 3 blocks and 100 bytes: the last chunk goes through the buffered
+        // descriptors, the stale tail of the longer destination is dropped
+        let data: Vec<u8> = (0..3 * 4096 + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(src, &data).unwrap();
+        std::fs::write(dst, vec![0xff_u8; 5 * 4096]).unwrap();
+        let ccfg = CopyConfig {
+            reader: ReadEngine::Direct,
+            writer: WriteEngine::Direct,
+            verify: true,
+            ..CopyConfig::default()
+        };
+        let timing = par_copy(src, dst, 4096, 2, &ParConfig::default(), &ccfg);
+        let copy = std::fs::read(dst).unwrap();
+        std::fs::remove_file(src).unwrap();
+        std::fs::remove_file(dst).unwrap();
+        assert!(timing.unwrap().verify.is_some());
+        assert_eq!(copy, data);
+    }
+}
@@ -1,13 +1,13 @@
+use crate::config::ParConfig;
+use crate::digest::Digests;
+use crate::schedule::{data_size, Chunks};
+use crate::utility::MovableMut;
 use crate::utility::*;
-use crate::utility::{dump, MovableMut};
 use crate::vec_io;
-use memmap2::MmapOptions;
-use std::io::Error as IOError;
-use std::io::ErrorKind as IOErrorKind;
+use crate::workers::{ParTiming, Workers};
 use std::io::{Read, Seek, SeekFrom};
-use std::os::raw::c_void;
 use std::os::unix::io::AsRawFd;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
 
 //-----------------------------------------------------------------------------
 pub fn par_read_all(
@@ -15,38 +15,41 @@ pub fn par_read_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
-    let mut threads = Vec::new();
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let th = std::thread::spawn(move || {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
             let mut file = std::fs::File::open(&fname)?;
-            file.seek(SeekFrom::Start(offset))?;
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let cs = thread_span.min(fsize - offset);
-            let slice = unsafe { std::slice::from_raw_parts_mut(ptr, cs as usize) };
-            let mut r = 0;
-            while r < slice.len() {
-                let b = r as usize;
-                let e = (b + chunk_size as usize).min(slice.len());
-                r += file.read(&mut slice[b..e])?;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            // seek only when the next chunk does not follow the previous one
+            let mut pos = None;
+            for r in chunks.iter(i) {
+                if pos != Some(r.start) {
+                    file.seek(SeekFrom::Start(chunks.offset(i, &r)))?;
+                }
+                pos = Some(r.end);
+                let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                file.read_exact(buf)?;
+                digests.record(r.start as u64, buf);
             }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf)?;
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -55,39 +58,42 @@ pub fn par_read_buf_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
-    let mut threads = Vec::new();
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let th = std::thread::spawn(move || {
-            let mut file = std::fs::File::open(&fname)?;
-            file.seek(SeekFrom::Start(offset))?;
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let file = std::fs::File::open(&fname)?;
             let mut br = std::io::BufReader::new(&file);
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let cs = thread_span.min(fsize - offset);
-            let slice = unsafe { std::slice::from_raw_parts_mut(ptr, cs as usize) };
-            let mut r = 0;
-            while r < slice.len() {
-                let b = r as usize;
-                let e = (b + chunk_size as usize).min(slice.len());
-                r += br.read(&mut slice[b..e])?;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            // seeking discards the buffered data, only seek on discontinuities
+            let mut pos = None;
+            for r in chunks.iter(i) {
+                if pos != Some(r.start) {
+                    br.seek(SeekFrom::Start(chunks.offset(i, &r)))?;
+                }
+                pos = Some(r.end);
+                let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                br.read_exact(buf)?;
+                digests.record(r.start as u64, buf);
             }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf)?;
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -96,49 +102,37 @@ pub fn par_read_pread_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
-    let file = std::fs::File::open(fname)?;
-    let fd = file.as_raw_fd();
-    let mut threads = Vec::new();
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let th = std::thread::spawn(move || {
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let cs = thread_span.min(fsize - offset);
-            let slice = unsafe { std::slice::from_raw_parts(ptr, cs as usize) };
-            let mut r = 0;
-            while r < slice.len() {
-                let b = r as usize;
-                let e = (b + chunk_size as usize).min(slice.len());
-                let sz = (e - b) as size_t;
-                let ret = unsafe {
-                    pread(
-                        fd,
-                        slice[b..e].as_ptr() as *mut c_void,
-                        sz,
-                        (offset as usize + b) as off_t,
-                    )
-                };
-                if ret < 0 {
-                    return Err(std::io::Error::last_os_error());
-                }
-                r += ret as usize;
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let file = std::fs::File::open(&fname)?;
+            let fd = file.as_raw_fd();
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            for r in chunks.iter(i) {
+                let offset = chunks.offset(i, &r);
+                let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                pread_exact(fd, buf, offset)?;
+                digests.record(r.start as u64, buf);
             }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf)?;
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -147,59 +141,45 @@ pub fn par_read_direct_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    if chunk_size % 512 != 0 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "O_DIRECT requires a chunk size multiple of 512'",
-        ));
-    }
-    let fsize = filebuf.len() as u64;
-    use std::os::unix::fs::OpenOptionsExt;
-    let file = std::fs::OpenOptions::new()
-        .read(true)
-        .custom_flags(libc::O_DIRECT)
-        .open(fname)?;
-    let fd = file.as_raw_fd();
-    let mut threads = Vec::new();
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let align = check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let th = std::thread::spawn(move || {
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let cs = thread_span.min(fsize - offset);
-            let slice = unsafe { std::slice::from_raw_parts(ptr, cs as usize) };
-            let mut r = 0;
-            while r < slice.len() {
-                let b = r as usize;
-                let e = (b + chunk_size as usize).min(slice.len());
-                let sz = (e - b) as size_t;
-                let ret = unsafe {
-                    pread(
-                        fd,
-                        slice[b..e].as_ptr() as *mut c_void,
-                        sz,
-                        (offset as usize + b) as off_t,
-                    )
-                };
-                if ret < 0 {
-                    return Err(std::io::Error::last_os_error());
-                }
-                r += ret as usize;
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            use std::os::unix::fs::OpenOptionsExt;
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(&fname)?;
+            let fd = file.as_raw_fd();
+            // the unaligned tail is read through a separate buffered descriptor
+            let tail_file = std::fs::File::open(&fname)?;
+            let direct_end = chunks.file_size(i) / align.offset * align.offset;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            for r in chunks.iter(i) {
+                let offset = chunks.offset(i, &r);
+                let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                pread_split(fd, tail_file.as_raw_fd(), buf, offset, direct_end)?;
+                digests.record(r.start as u64, buf);
             }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf)?;
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -208,39 +188,49 @@ pub fn par_read_mmap_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
-    let file = std::sync::Arc::new(std::fs::File::open(fname)?);
-    let mut threads = Vec::new();
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    // files are mapped, and prefaulted, once before the workers start: which
+    // chunks a worker touches is only known at run time with a dynamic
+    // schedule; forked workers inherit the mappings but fault the pages again
+    let mut maps = Vec::new();
+    let mut prefault = std::time::Duration::ZERO;
+    for f in 0..chunks.num_files() {
+        let file = std::fs::File::open(chunks.file_name(fname, f))?;
+        let (mmap, t) = map_file(&file, &cfg.mmap)?;
+        maps.push(mmap);
+        prefault += t;
+    }
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let file = file.clone();
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let th = std::thread::spawn(move || {
-            let mmap = unsafe { MmapOptions::new().offset(offset).map(&*file)? };
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let cs = thread_span.min(fsize - offset);
-            let slice: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(ptr, cs as usize) };
-            let mut r = 0;
-            while r < slice.len() {
-                let b = r as usize;
-                let e = (b + chunk_size as usize).min(slice.len());
-                slice[b..e].copy_from_slice(&mmap[b..e]);
-                r += e - b;
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let map = &maps[if maps.len() > 1 { i as usize } else { 0 }];
+        let src = Movable(map.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        workers.spawn(i, move |worker| {
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            for r in chunks.iter(i) {
+                let offset = chunks.offset(i, &r) as usize;
+                let src = unsafe { src.slice(offset..offset + r.len()) };
+                let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                buf.copy_from_slice(src);
+                digests.record(r.start as u64, buf);
             }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf)?;
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    timing.prefault = Some(prefault);
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 //-----------------------------------------------------------------------------
 pub fn par_read_vec_all(
@@ -248,32 +238,252 @@ pub fn par_read_vec_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
-    let mut threads = Vec::new();
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let th = std::thread::spawn(move || {
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
             let mut file = std::fs::File::open(&fname)?;
-            let cs = thread_span.min(fsize - offset);
-            let slice: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(ptr, cs as usize) };
-            vec_io::read_vec_slice_offset(&mut file, slice, chunk_size, offset as isize)?;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            // one vectored read per run of consecutive chunks
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r) as isize;
+                let slice = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                vec_io::read_vec_slice_offset(&mut file, slice, chunk_size, offset)?;
+                digests.record(r.start as u64, slice);
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// one preadv2 call per run of consecutive chunks with the `--rwf` flags; with
+// RWF_HIPRI the file is opened with O_DIRECT, polling only applies to direct i/o
+#[cfg(all(feature = "par_read_preadv2_all", target_os = "linux"))]
+pub fn par_read_preadv2_all(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let direct = cfg.rwf & RWF_HIPRI != 0;
+    let align = if direct {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?.offset
+    } else {
+        1
+    };
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let flags = cfg.rwf;
+    let eagain = std::sync::Arc::new(SharedCounter::new());
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let eagain = eagain.clone();
+        workers.spawn(i, move |worker| {
+            use std::os::unix::fs::OpenOptionsExt;
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(if direct { libc::O_DIRECT } else { 0 })
+                .open(&fname)?;
+            // the unaligned tail is read through a separate buffered descriptor
+            let tail_file = std::fs::File::open(&fname)?;
+            let direct_end = chunks.file_size(i) / align * align;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r);
+                let slice = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                let d = (direct_end.saturating_sub(offset) as usize).min(slice.len());
+                let (head, tail) = slice.split_at_mut(d);
+                if !head.is_empty() {
+                    let n = vec_io::read_vec_slice_offset_flags(
+                        &file,
+                        head,
+                        chunk_size,
+                        offset as off_t,
+                        flags,
+                    )?;
+                    eagain.fetch_add(n, std::sync::atomic::Ordering::Relaxed);
+                }
+                if !tail.is_empty() {
+                    pread_exact(tail_file.as_raw_fd(), tail, offset + d as u64)?;
+                }
+                digests.record(r.start as u64, slice);
+            }
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    if flags & RWF_NOWAIT != 0 {
+        timing
+            .counters
+            .push(("EAGAIN", eagain.load(std::sync::atomic::Ordering::Relaxed)));
+    }
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// O_DIRECT, `queue_depth` requests in flight per worker
+#[cfg(all(feature = "par_read_aio_all", target_os = "linux"))]
+pub fn par_read_aio_all(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use crate::aio::{AioContext, IOCB_CMD_PREAD};
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let align = check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = chunks.free_slots(i, depth)?;
+        workers.spawn(i, move |worker| {
+            use std::os::unix::fs::OpenOptionsExt;
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(&fname)?;
+            // the unaligned tail is read through a separate buffered descriptor
+            let tail_file = std::fs::File::open(&fname)?;
+            let direct_end = chunks.file_size(i) / align.offset * align.offset;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            let ctx = AioContext::new(depth)?;
+            worker.start();
+            // the chunk holding the tail is read once the others are done
+            let mut tails = Vec::new();
+            let reqs = chunks
+                .iter(i)
+                .filter(|r| {
+                    let aligned = chunks.offset(i, r) + r.len() as u64 <= direct_end;
+                    if !aligned {
+                        tails.push(r.clone());
+                    }
+                    aligned
+                })
+                .map(|r| {
+                    let offset = chunks.offset(i, &r);
+                    let len = r.len();
+                    (
+                        unsafe { mb.slice_mut(slots.take(r)) }.as_mut_ptr(),
+                        len,
+                        offset,
+                    )
+                });
+            let complete = |buf: *mut u8, len, offset| {
+                let data = unsafe { std::slice::from_raw_parts(buf, len) };
+                digests.record(chunks.data_offset(i, offset), data);
+                slots.release(buf as usize - mb.0 as usize);
+            };
+            unsafe { ctx.run(file.as_raw_fd(), IOCB_CMD_PREAD, reqs, complete)? };
+            for r in tails {
+                let offset = chunks.offset(i, &r);
+                let slot = slots.take(r.clone());
+                let buf = unsafe { mb.slice_mut(slot.clone()) };
+                pread_split(
+                    file.as_raw_fd(),
+                    tail_file.as_raw_fd(),
+                    buf,
+                    offset,
+                    direct_end,
+                )?;
+                digests.record(r.start as u64, buf);
+                slots.release(slot.start);
+            }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf)?;
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// `queue_depth` control blocks in flight per worker
+#[cfg(feature = "par_read_posix_aio_all")]
+pub fn par_read_posix_aio_all(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use crate::posix_aio;
+    // glibc runs the requests on helper threads that outlive the run
+    crate::workers::threads_only(cfg, "POSIX AIO")?;
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = chunks.free_slots(i, depth)?;
+        workers.spawn(i, move |worker| {
+            let file = std::fs::File::open(&fname)?;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            worker.start();
+            let reqs = chunks.iter(i).map(|r| {
+                let offset = chunks.offset(i, &r);
+                let len = r.len();
+                (
+                    unsafe { mb.slice_mut(slots.take(r)) }.as_mut_ptr(),
+                    len,
+                    offset,
+                )
+            });
+            let complete = |buf: *mut u8, len, offset| {
+                let data = unsafe { std::slice::from_raw_parts(buf, len) };
+                digests.record(chunks.data_offset(i, offset), data);
+                slots.release(buf as usize - mb.0 as usize);
+            };
+            unsafe { posix_aio::run(file.as_raw_fd(), libc::LIO_READ, depth, reqs, complete) }
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -285,93 +495,108 @@ pub fn par_read_uring_vec_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let fsize = filebuf.len();
-    let num_threads = num_threads as usize;
-    let num_chunks = ((fsize as u64 + chunk_size - 1) / chunk_size) as usize;
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let align = if cfg!(feature = "uring_direct") {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?.offset
+    } else {
+        1
+    };
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     let chunk_size = chunk_size as usize;
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let t = Instant::now();
     for i in 0..num_threads {
-        let num_chunks = chunks_per_thread.min(num_chunks - chunks_per_thread * (num_threads -1));
-        let offset = thread_span * i;
-        let mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
         use std::os::unix::fs::OpenOptionsExt;
-        let th = std::thread::spawn(move || {
-            let mut file = if cfg!(feature = "uring_direct") {
+        workers.spawn(i, move |worker| {
+            let file = if cfg!(feature = "uring_direct") {
                 std::fs::OpenOptions::new()
                     .read(true)
                     .custom_flags(libc::O_DIRECT)
-                    .open(fname)?
+                    .open(&fname)?
             } else {
-                std::fs::OpenOptions::new()
-                    .read(true)
-                    .open(fname)?
-            };
-            let ptr = match mb.get()  {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
+                std::fs::OpenOptions::new().read(true).open(&fname)?
             };
-            let bytes = (num_chunks * chunk_size).min(fsize - offset);
-            //@warning: it is not possible to use iou to read data by dynamically creating
-            //a vector of mutable slices, it is therefore required to create manually an
-            //array of IoVec structs which are compatible with IoSliceMut
-            // - IoSliceMut contains an std::sys::io::IoSlice
-            // - sts::sys::io::IoSlice contains an iovec
-            // - iovec is declared as:
-            //      #[repr(C)]
-            //      pub struct iovec {
-            //        pub iov_base: *mut c_void,
-            //        pub iov_len: size_t,
-            //      }
-            // - IoVec is declared the same as iovec
-            // - Therefore: as slice of Vec<IoVec> can be cast to a slice of Vec<IoSliceMut>
-            let mut bufs = Vec::new();
-             
-            for i in 0..num_chunks {
-                let b = i as usize * chunk_size as usize;
-                let e = (b + chunk_size).min(fsize);
-                let iv = unsafe{IoVec{iov_base: ptr.offset(b as isize) as *mut c_void, iov_len: e - b }};
-                bufs.push(iv);
+            // the unaligned tail is read through a separate buffered descriptor
+            let tail_file = std::fs::File::open(&fname)?;
+            let direct_end = chunks.file_size(i) / align * align;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
             }
-            let ioslice = unsafe {std::slice::from_raw_parts_mut(bufs.as_mut_ptr() as *mut std::io::IoSliceMut, bufs.len())};
-            let entries = num_chunks as u32;
-            let n = {
-                let mut io_uring = iou::IoUring::new(entries)?;
-                unsafe {
-                    let mut sq = io_uring.sq();
-                    let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Failed to prepare io_uring submission queue",
-                    ))?;
-                    sqe.prep_read_vectored(file.as_raw_fd(), ioslice, offset as u64);
-                    io_uring.sq().submit()?;
+            let mut io_uring = iou::IoUring::new(1)?;
+            worker.start();
+            // one vectored read per run of consecutive chunks
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r);
+                let slice = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                let bytes = (direct_end.saturating_sub(offset) as usize).min(slice.len());
+                //@warning: it is not possible to use iou to read data by dynamically creating
+                //a vector of mutable slices, it is therefore required to create manually an
+                //array of IoVec structs which are compatible with IoSliceMut
+                // - IoSliceMut contains an std::sys::io::IoSlice
+                // - sts::sys::io::IoSlice contains an iovec
+                // - iovec is declared as:
+                //      #[repr(C)]
+                //      pub struct iovec {
+                //        pub iov_base: *mut c_void,
+                //        pub iov_len: size_t,
+                //      }
+                // - IoVec is declared the same as iovec
+                // - Therefore: as slice of Vec<IoVec> can be cast to a slice of Vec<IoSliceMut>
+                let mut bufs = Vec::new();
+                for b in (0..bytes).step_by(chunk_size) {
+                    let e = (b + chunk_size).min(bytes);
+                    bufs.push(IoVec {
+                        iov_base: slice[b..e].as_mut_ptr() as *mut std::os::raw::c_void,
+                        iov_len: e - b,
+                    });
                 }
-
-                let mut cq = io_uring.cq();
-                let cqe = cq.wait_for_cqe()?;
-                cqe.result()? as usize
-            };
-            if n != (num_chunks * chunk_size) as usize {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("seq_write_uring_all: Failed to write data from io_uring queue, requested: {}, written: {}", chunk_size * num_chunks, n).as_str()
-                ));
+                let ioslice = unsafe {
+                    std::slice::from_raw_parts_mut(
+                        bufs.as_mut_ptr() as *mut std::io::IoSliceMut,
+                        bufs.len(),
+                    )
+                };
+                let n = if bytes == 0 {
+                    0
+                } else {
+                    unsafe {
+                        let mut sq = io_uring.sq();
+                        let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::other(
+                            "Failed to prepare io_uring submission queue",
+                        ))?;
+                        sqe.prep_read_vectored(file.as_raw_fd(), ioslice, offset);
+                        io_uring.sq().submit()?;
+                    }
+                    let mut cq = io_uring.cq();
+                    let cqe = cq.wait_for_cqe()?;
+                    cqe.result()? as usize
+                };
+                if n != bytes {
+                    return Err(std::io::Error::other(
+                        format!("par_read_uring_vec_all: Failed to read data from io_uring queue, requested: {}, read: {}", bytes, n).as_str()
+                    ));
+                }
+                if bytes < slice.len() {
+                    let tail = &mut slice[bytes..];
+                    pread_exact(tail_file.as_raw_fd(), tail, offset + bytes as u64)?;
+                }
+                digests.record(r.start as u64, slice);
             }
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    dump(&filebuf);
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
 
-
 //-----------------------------------------------------------------------------
 // @warning will normally fail for total size > (2GiB - 4kiB), limit imposed
 // by vectored i/o, so partial reads/writes must be handled
@@ -381,67 +606,307 @@ pub fn par_read_uring_all(
     chunk_size: u64,
     num_threads: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let fsize = filebuf.len() as u64;
-    let thread_span = (fsize + num_threads - 1) / num_threads;
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let align = if cfg!(feature = "uring_direct") {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?.offset
+    } else {
+        1
+    };
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
     for i in 0..num_threads {
-        let offset = thread_span * i;
-        let mut mb = unsafe { MovableMut(filebuf.as_mut_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
         use std::os::unix::fs::OpenOptionsExt;
-        let th = std::thread::spawn(move || {
-            let mut file = if cfg!(feature = "uring_direct") {
+        workers.spawn(i, move |worker| {
+            let file = if cfg!(feature = "uring_direct") {
                 std::fs::OpenOptions::new()
                     .read(true)
                     .custom_flags(libc::O_DIRECT)
-                    .open(fname)?
-            } else {
-                std::fs::OpenOptions::new()
-                    .read(true)
-                    .open(fname)?
-            };
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let bytes = if i != num_threads - 1 {
-                thread_span
+                    .open(&fname)?
             } else {
-                fsize - (num_threads - 1) * thread_span
+                std::fs::OpenOptions::new().read(true).open(&fname)?
             };
-            let slice = unsafe { std::slice::from_raw_parts_mut(ptr, bytes as usize) };
-            let entries = 1;
-            let n = {
-                let mut io_uring = iou::IoUring::new(entries)?;
-                unsafe {
-                    let mut sq = io_uring.sq();
-                    let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Failed to prepare io_uring submission queue",
-                    ))?;
-                    sqe.prep_read(file.as_raw_fd(), slice, offset);
-                    io_uring.sq().submit()?;
+            // the unaligned tail is read through a separate buffered descriptor
+            let tail_file = std::fs::File::open(&fname)?;
+            let direct_end = chunks.file_size(i) / align * align;
+            for r in chunks.placed(i) {
+                worker.place_mut(unsafe { mb.slice_mut(r) })?;
+            }
+            let mut io_uring = iou::IoUring::new(1)?;
+            worker.start();
+            // one read per run of consecutive chunks
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r);
+                let slice = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                let bytes = (direct_end.saturating_sub(offset) as usize).min(slice.len());
+                let n = if bytes == 0 {
+                    0
+                } else {
+                    unsafe {
+                        let mut sq = io_uring.sq();
+                        let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::other(
+                            "Failed to prepare io_uring submission queue",
+                        ))?;
+                        sqe.prep_read(file.as_raw_fd(), &mut slice[..bytes], offset);
+                        io_uring.sq().submit()?;
+                    }
+                    let mut cq = io_uring.cq();
+                    let cqe = cq.wait_for_cqe()?;
+                    cqe.result()? as usize
+                };
+                if n != bytes {
+                    return Err(std::io::Error::other(
+                        format!("par_read_uring_all: Failed to read data from io_uring queue, requested: {}, read: {}", bytes, n).as_str()
+                    ));
                 }
-                let mut cq = io_uring.cq();
-                let cqe = cq.wait_for_cqe()?;
-                cqe.result()? as usize
-            };
-            if n != bytes as usize {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("seq_read_uring_all: Failed to read data from io_uring queue, requested: {}, read: {}", bytes, n).as_str()
-                ));
+                if bytes < slice.len() {
+                    let tail = &mut slice[bytes..];
+                    pread_exact(tail_file.as_raw_fd(), tail, offset + bytes as u64)?;
+                }
+                digests.record(r.start as u64, slice);
             }
             Ok(())
         });
-        threads.push(th);
     }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// one glommio executor per worker
+#[cfg(feature = "par_glommio_read")]
+pub fn par_glommio_read(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use glommio::{io::BufferedFile, LocalExecutor};
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let ex = LocalExecutor::default();
+            ex.run(async {
+                let file = BufferedFile::open(&fname).await?;
+                for r in chunks.placed(i) {
+                    worker.place_mut(unsafe { mb.slice_mut(r) })?;
+                }
+                worker.start();
+                for r in chunks.iter(i) {
+                    let offset = chunks.offset(i, &r);
+                    let data = file.read_at(offset, r.len()).await?;
+                    let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                    buf.copy_from_slice(&data);
+                    digests.record(r.start as u64, buf);
+                }
+                file.close().await?;
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// one glommio executor per worker
+#[cfg(feature = "par_glommio_dma_read")]
+pub fn par_glommio_dma_read(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use glommio::{io::DmaFile, LocalExecutor};
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    // `read_at` aligns the unaligned tail internally
+    check_dio_align(fname, None, chunk_size, None)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let ex = LocalExecutor::default();
+            ex.run(async {
+                let file = DmaFile::open(&fname).await?;
+                for r in chunks.placed(i) {
+                    worker.place_mut(unsafe { mb.slice_mut(r) })?;
+                }
+                worker.start();
+                for r in chunks.iter(i) {
+                    let offset = chunks.offset(i, &r);
+                    let data = file.read_at(offset, r.len()).await?;
+                    let buf = unsafe { mb.slice_mut(chunks.buf(i, r.clone())) };
+                    buf.copy_from_slice(&data);
+                    digests.record(r.start as u64, buf);
+                }
+                file.close().await?;
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
 
-    join_and_check!(threads);
+//-----------------------------------------------------------------------------
+// one current-thread runtime per worker with `queue_depth` tasks, each owning a
+// file handle since tokio files have no positional reads
+#[cfg(feature = "par_read_tokio_all")]
+pub fn par_read_tokio_all(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = Arc::new(chunks.free_slots(i, depth)?);
+        workers.spawn(i, move |worker| {
+            let rt = tokio::runtime::Builder::new_current_thread().build()?;
+            rt.block_on(async {
+                let mut files = Vec::with_capacity(depth);
+                for _ in 0..depth {
+                    files.push(tokio::fs::File::open(&fname).await?);
+                }
+                for r in chunks.placed(i) {
+                    worker.place_mut(unsafe { mb.slice_mut(r) })?;
+                }
+                worker.start();
+                let mut tasks = Vec::with_capacity(depth);
+                for (s, mut file) in files.into_iter().enumerate() {
+                    let mb = MovableMut(mb.0);
+                    let chunks = chunks.clone();
+                    let digests = digests.clone();
+                    let slots = slots.clone();
+                    tasks.push(tokio::spawn(async move {
+                        for r in chunks.iter(i).share(s, depth) {
+                            file.seek(SeekFrom::Start(chunks.offset(i, &r))).await?;
+                            let slot = slots.take(r.clone());
+                            let buf = unsafe { mb.slice_mut(slot.clone()) };
+                            file.read_exact(buf).await?;
+                            digests.record(r.start as u64, buf);
+                            slots.release(slot.start);
+                        }
+                        Ok::<(), std::io::Error>(())
+                    }));
+                }
+                for t in tasks {
+                    t.await??;
+                }
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
+}
 
-    let e = t.elapsed();
-    dump(&filebuf);
-    Ok(e)
+//-----------------------------------------------------------------------------
+// one tokio-uring runtime per worker with `queue_depth` tasks; the runtime owns
+// the buffers of the requests in flight, data is copied out of them
+#[cfg(all(feature = "par_read_tokio_uring_all", target_os = "linux"))]
+pub fn par_read_tokio_uring_all(
+    fname: &str,
+    chunk_size: u64,
+    num_threads: u64,
+    filebuf: &mut [u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use tokio_uring::buf::IoBuf;
+    let fsize = data_size(fname, cfg.variant.files, num_threads, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    for i in 0..num_threads {
+        let mb = MovableMut(filebuf.as_mut_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = Arc::new(chunks.free_slots(i, depth)?);
+        workers.spawn(i, move |worker| {
+            tokio_uring::start(async {
+                let file = std::rc::Rc::new(tokio_uring::fs::File::open(&fname).await?);
+                for r in chunks.placed(i) {
+                    worker.place_mut(unsafe { mb.slice_mut(r) })?;
+                }
+                worker.start();
+                let mut tasks = Vec::with_capacity(depth);
+                for s in 0..depth {
+                    let file = file.clone();
+                    let mb = MovableMut(mb.0);
+                    let chunks = chunks.clone();
+                    let digests = digests.clone();
+                    let slots = slots.clone();
+                    tasks.push(tokio_uring::spawn(async move {
+                        let mut buf = Vec::with_capacity(chunk_size as usize);
+                        for r in chunks.iter(i).share(s, depth) {
+                            let offset = chunks.offset(i, &r);
+                            let bytes = r.len();
+                            let (ret, slice) = file.read_at(buf.slice(..bytes), offset).await;
+                            buf = slice.into_inner();
+                            let n = ret?;
+                            if n != bytes {
+                                return Err(std::io::Error::other(
+                                    format!("par_read_tokio_uring_all: Failed to read data, requested: {}, read: {}", bytes, n).as_str()
+                                ));
+                            }
+                            let slot = slots.take(r.clone());
+                            let dst = unsafe { mb.slice_mut(slot.clone()) };
+                            dst.copy_from_slice(&buf[..n]);
+                            digests.record(r.start as u64, dst);
+                            slots.release(slot.start);
+                        }
+                        Ok(())
+                    }));
+                }
+                for t in tasks {
+                    t.await??;
+                }
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    digests.dump_unhashed(filebuf)?;
+    Ok(timing)
 }
@@ -1,12 +1,41 @@
+use crate::config::{Flush, ParConfig};
+use crate::digest::Digests;
+use crate::schedule::{Chunks, Partition};
 use crate::utility::*;
 use crate::vec_io;
-use memmap2::MmapOptions;
-use std::io::Error as IOError;
-use std::io::ErrorKind as IOErrorKind;
+use crate::workers::{ParTiming, Workers};
 use std::io::{Seek, SeekFrom, Write};
-use std::os::raw::c_void;
 use std::os::unix::io::AsRawFd;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+
+//-----------------------------------------------------------------------------
+/// Create the file, or one file per worker, before the workers open them;
+/// `set_len` also sizes the files.
+fn create_files(fname: &str, chunks: &Chunks, set_len: bool) -> std::io::Result<()> {
+    //@todo: use fallocate
+    for i in 0..chunks.num_files() {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(chunks.file_name(fname, i))?;
+        if set_len {
+            file.set_len(chunks.file_size(i))?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop stale data of a previous, longer file after writing it.
+fn truncate_files(fname: &str, chunks: &Chunks) -> std::io::Result<()> {
+    for i in 0..chunks.num_files() {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(chunks.file_name(fname, i))?
+            .set_len(chunks.file_size(i))?;
+    }
+    Ok(())
+}
 
 //-----------------------------------------------------------------------------
 pub fn par_write_all(
@@ -15,51 +44,43 @@ pub fn par_write_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&fname)?;
-        }
-        let th = std::thread::spawn(move || {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
             let mut file = std::fs::OpenOptions::new().write(true).open(&fname)?;
-            file.seek(SeekFrom::Start(offset))?;
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let slice =
-                unsafe { std::slice::from_raw_parts(ptr, (chunk_size * num_chunks) as usize) };
-            let mut w = 0;
-            let bytes = num_chunks * chunk_size;
-            while w < bytes {
-                let b = w as usize;
-                let e = (b + chunk_size as usize).min(bytes as usize);
-                w += file.write(&slice[b..e])? as u64;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            // seek only when the next chunk does not follow the previous one
+            let mut pos = None;
+            for r in chunks.iter(i) {
+                if pos != Some(r.start) {
+                    file.seek(SeekFrom::Start(chunks.offset(i, &r)))?;
+                }
+                pos = Some(r.end);
+                let buf = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                file.write_all(buf)?;
+                digests.record(r.start as u64, buf);
             }
             file.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -69,53 +90,45 @@ pub fn par_write_buf_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&fname)?;
-        }
-        let th = std::thread::spawn(move || {
-            let mut file = std::fs::OpenOptions::new().write(true).open(&fname)?;
-            file.seek(SeekFrom::Start(offset))?;
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let slice =
-                unsafe { std::slice::from_raw_parts(ptr, (chunk_size * num_chunks) as usize) };
-            let mut w = 0;
-            let bytes = num_chunks * chunk_size;
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let file = std::fs::OpenOptions::new().write(true).open(&fname)?;
             use std::io::BufWriter;
-            let mut bw = BufWriter::new(&mut file);
-            while w < bytes {
-                let b = w as usize;
-                let e = (b + chunk_size as usize).min(bytes as usize);
-                w += bw.write(&slice[b..e])? as u64;
+            let mut bw = BufWriter::new(&file);
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            // seeking flushes the buffered data, only seek on discontinuities
+            let mut pos = None;
+            for r in chunks.iter(i) {
+                if pos != Some(r.start) {
+                    bw.seek(SeekFrom::Start(chunks.offset(i, &r)))?;
+                }
+                pos = Some(r.end);
+                let buf = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                bw.write_all(buf)?;
+                digests.record(r.start as u64, buf);
             }
             bw.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -125,55 +138,48 @@ pub fn par_write_direct_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    // the files hold whole chunks of an aligned size: no unaligned tail
+    check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&fname)?;
-        }
-        let th = std::thread::spawn(move || {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
             use std::os::unix::fs::OpenOptionsExt;
             let mut file = std::fs::OpenOptions::new()
                 .write(true)
                 .custom_flags(libc::O_DIRECT)
                 .open(&fname)?;
-            file.seek(SeekFrom::Start(offset))?;
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let slice =
-                unsafe { std::slice::from_raw_parts(ptr, (chunk_size * num_chunks) as usize) };
-            let mut w = 0;
-            let bytes = num_chunks * chunk_size;
-            while w < bytes {
-                let b = w as usize;
-                let e = (b + chunk_size as usize).min(bytes as usize);
-                w += file.write(&slice[b..e])? as u64;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            let mut pos = None;
+            for r in chunks.iter(i) {
+                if pos != Some(r.start) {
+                    file.seek(SeekFrom::Start(chunks.offset(i, &r)))?;
+                }
+                pos = Some(r.end);
+                let buf = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                file.write_all(buf)?;
+                digests.record(r.start as u64, buf);
             }
             file.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -183,63 +189,39 @@ pub fn par_write_pwrite_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&fname)?;
-        }
-        let fname = fname.to_owned();
-        let th = std::thread::spawn(move || {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
             let mut file = std::fs::OpenOptions::new().write(true).open(&fname)?;
             let fd = file.as_raw_fd();
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let slice =
-                unsafe { std::slice::from_raw_parts(ptr, (chunk_size * num_chunks) as usize) };
-            let mut w = 0;
-            let bytes = num_chunks * chunk_size;
-            while w < bytes {
-                let b = w as usize;
-                let e = (b + chunk_size as usize).min(bytes as usize);
-                let sz = (e - b) as size_t;
-                let ret = unsafe {
-                    pwrite(
-                        fd,
-                        slice[b..e].as_ptr() as *mut c_void,
-                        sz,
-                        (offset as usize + b) as off_t,
-                    )
-                };
-                if ret < 0 {
-                    return Err(std::io::Error::last_os_error());
-                }
-                w += ret as u64;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            for r in chunks.iter(i) {
+                let offset = chunks.offset(i, &r);
+                let buf = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                pwrite_all(fd, buf, offset)?;
+                digests.record(r.start as u64, buf);
             }
             file.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -249,108 +231,335 @@ pub fn par_write_mmap_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let mmap_cfg = &cfg.mmap;
+    // slowest worker's time spent flushing, in ns
+    let writeback = std::sync::Arc::new(SharedCounter::new());
+    // the mapping must not extend past the end of the file
+    create_files(fname, &chunks, true)?;
+    // files are mapped, and prefaulted, once before the workers start as in
+    // `par_read_mmap_all`; empty files are not mapped
+    let mut maps = Vec::new();
+    let mut prefault = std::time::Duration::ZERO;
+    for f in 0..chunks.num_files() {
+        let len = chunks.file_size(f) as usize;
+        if len == 0 {
+            maps.push(None);
+            continue;
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(chunks.file_name(fname, f))?;
+        let (mmap, t) = map_file_mut(&file, len, mmap_cfg)?;
+        maps.push(Some(mmap));
+        prefault += t;
+    }
+    let ptrs: Vec<_> = maps
+        .iter_mut()
+        .map(|m| m.as_mut().map_or(std::ptr::null_mut(), |m| m.as_mut_ptr()))
+        .collect();
+    let maps = Arc::new(maps);
     for i in 0..num_threads {
-        let offset = num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let fname = fname.to_owned();
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
+        let mb = Movable(filebuf.as_ptr());
+        let f = if chunks.num_files() > 1 {
+            i as usize
         } else {
-            num_chunks_last_thread
+            0
         };
-        //@todo: use fallocate
+        let dst = MovableMut(ptrs[f]);
+        let maps = maps.clone();
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let mmap_cfg = mmap_cfg.clone();
+        let writeback = writeback.clone();
+        workers.spawn(i, move |worker| {
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            let mut t = std::time::Duration::ZERO;
+            // runs of consecutive bytes written, flushed at the end with
+            // `Flush::Thread`
+            let mut written: Vec<std::ops::Range<usize>> = Vec::new();
+            for r in chunks.iter(i) {
+                let offset = chunks.offset(i, &r) as usize;
+                let len = r.len();
+                let buf = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                unsafe { dst.slice_mut(offset..offset + len) }.copy_from_slice(buf);
+                digests.record(r.start as u64, buf);
+                let mmap = maps[f].as_ref().unwrap();
+                match mmap_cfg.flush {
+                    Flush::Chunk => t += flush_range(mmap, offset, len, &mmap_cfg)?,
+                    Flush::Thread => match written.last_mut() {
+                        Some(w) if w.end == offset => w.end += len,
+                        _ => written.push(offset..offset + len),
+                    },
+                    Flush::End => {}
+                }
+            }
+            for w in written {
+                let mmap = maps[f].as_ref().unwrap();
+                t += flush_range(mmap, w.start, w.len(), &mmap_cfg)?;
+            }
+            writeback.fetch_max(t.as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    timing.prefault = Some(prefault);
+    let mut t =
+        std::time::Duration::from_nanos(writeback.load(std::sync::atomic::Ordering::Relaxed));
+    if mmap_cfg.flush == Flush::End {
+        for m in maps.iter().flatten() {
+            timing.after += flush_range(m, 0, m.len(), mmap_cfg)?;
+        }
+        t = timing.after;
+    }
+    timing.writeback = Some(t);
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+pub fn par_write_vec_all(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&fname)?;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            // one vectored write per run of consecutive chunks
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r) as isize;
+                let slice = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                vec_io::write_vec_slice_offset(&mut file, slice, chunk_size, offset)?;
+                digests.record(r.start as u64, slice);
+            }
+            file.flush()?;
+            Ok(())
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// one pwritev2 call per run of consecutive chunks with the `--rwf` flags; with
+// RWF_HIPRI the file is opened with O_DIRECT, polling only applies to direct i/o;
+// buffered RWF_NOWAIT writes fail with EOPNOTSUPP where not supported
+#[cfg(all(feature = "par_write_pwritev2_all", target_os = "linux"))]
+pub fn par_write_pwritev2_all(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let direct = cfg.rwf & RWF_HIPRI != 0;
+    if direct {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    }
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let flags = cfg.rwf;
+    let eagain = std::sync::Arc::new(SharedCounter::new());
+    create_files(fname, &chunks, false)?;
+    // appends ignore the offset: each file must be written by a single worker,
+    // in file order, starting from an empty file
+    if flags & RWF_APPEND != 0 {
+        if chunks.num_files() < num_threads || cfg.variant.partition == Partition::RandomPermutation
         {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--rwf=append requires a single worker per file writing in file order: \
+                 one thread or --files=per-thread, and no random permutation",
+            ));
+        }
+        for i in 0..chunks.num_files() {
             std::fs::OpenOptions::new()
                 .write(true)
-                .create(true)
-                .open(&fname)?;
+                .open(chunks.file_name(fname, i))?
+                .set_len(0)?;
         }
-        let th = std::thread::spawn(move || {
-            let mut file = std::fs::OpenOptions::new()
-                .read(true)
+    }
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let eagain = eagain.clone();
+        workers.spawn(i, move |worker| {
+            use std::os::unix::fs::OpenOptionsExt;
+            let file = std::fs::OpenOptions::new()
                 .write(true)
+                .custom_flags(if direct { libc::O_DIRECT } else { 0 })
                 .open(&fname)?;
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
-            };
-            let bytes = num_chunks * chunk_size;
-            let slice =
-                unsafe { std::slice::from_raw_parts(ptr, (chunk_size * num_chunks) as usize) };
-            let mut mmap = unsafe {
-                MmapOptions::new()
-                    .len(bytes as usize)
-                    .offset(offset)
-                    .map_mut(&file)?
-            };
-            let mut w = 0;
-            while w < bytes {
-                let b = w as usize;
-                let e = (b + chunk_size as usize).min(bytes as usize);
-                mmap[b..e].copy_from_slice(&slice[b..e]);
-                w += (e - b) as u64;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r) as off_t;
+                let slice = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                let n =
+                    vec_io::write_vec_slice_offset_flags(&file, slice, chunk_size, offset, flags)?;
+                eagain.fetch_add(n, std::sync::atomic::Ordering::Relaxed);
+                digests.record(r.start as u64, slice);
             }
-            file.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    if flags & RWF_NOWAIT != 0 {
+        timing
+            .counters
+            .push(("EAGAIN", eagain.load(std::sync::atomic::Ordering::Relaxed)));
+    }
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
-pub fn par_write_vec_all(
+// O_DIRECT, `queue_depth` requests in flight per worker
+#[cfg(all(feature = "par_write_aio_all", target_os = "linux"))]
+pub fn par_write_aio_all(
     fname: &str,
     chunk_size: u64,
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use crate::aio::{AioContext, IOCB_CMD_PWRITE};
+    check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = chunk_size * num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = chunks.free_slots(i, depth)?;
+        workers.spawn(i, move |worker| {
+            use std::os::unix::fs::OpenOptionsExt;
+            let file = std::fs::OpenOptions::new()
                 .write(true)
-                .create(true)
+                .custom_flags(libc::O_DIRECT)
                 .open(&fname)?;
-        }
-        let fname = fname.to_owned();
-        let th = std::thread::spawn(move || {
-            let mut file = std::fs::OpenOptions::new().write(true).open(&fname)?;
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            let ctx = AioContext::new(depth)?;
+            worker.start();
+            let reqs = chunks.iter(i).map(|r| {
+                let offset = chunks.offset(i, &r);
+                let len = r.len();
+                (
+                    unsafe { mb.slice(slots.take(r)) }.as_ptr() as *mut u8,
+                    len,
+                    offset,
+                )
+            });
+            let complete = |buf: *mut u8, len, offset| {
+                let data = unsafe { std::slice::from_raw_parts(buf, len) };
+                digests.record(chunks.data_offset(i, offset), data);
+                slots.release(buf as usize - mb.0 as usize);
             };
-            let bytes = num_chunks * chunk_size;
-            let slice = unsafe { std::slice::from_raw_parts(ptr, bytes as usize) };
-            vec_io::write_vec_slice_offset(&mut file, slice, chunk_size, offset as isize)?;
-            file.flush()?;
-            Ok(())
+            unsafe { ctx.run(file.as_raw_fd(), IOCB_CMD_PWRITE, reqs, complete) }
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
+}
 
-    let e = t.elapsed();
-    Ok(e)
+//-----------------------------------------------------------------------------
+// `queue_depth` control blocks in flight per worker
+#[cfg(feature = "par_write_posix_aio_all")]
+pub fn par_write_posix_aio_all(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use crate::posix_aio;
+    // glibc runs the requests on helper threads that outlive the run
+    crate::workers::threads_only(cfg, "POSIX AIO")?;
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    create_files(fname, &chunks, false)?;
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = chunks.free_slots(i, depth)?;
+        workers.spawn(i, move |worker| {
+            let file = std::fs::OpenOptions::new().write(true).open(&fname)?;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            worker.start();
+            let reqs = chunks.iter(i).map(|r| {
+                let offset = chunks.offset(i, &r);
+                let len = r.len();
+                (
+                    unsafe { mb.slice(slots.take(r)) }.as_ptr() as *mut u8,
+                    len,
+                    offset,
+                )
+            });
+            let complete = |buf: *mut u8, len, offset| {
+                let data = unsafe { std::slice::from_raw_parts(buf, len) };
+                digests.record(chunks.data_offset(i, offset), data);
+                slots.release(buf as usize - mb.0 as usize);
+            };
+            unsafe { posix_aio::run(file.as_raw_fd(), libc::LIO_WRITE, depth, reqs, complete) }
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
 
 //-----------------------------------------------------------------------------
@@ -363,88 +572,77 @@ pub fn par_write_uring_vec_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    if cfg!(feature = "uring_direct") {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    }
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let chunk_size = chunk_size as usize;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = chunk_size * num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&fname)?;
-        }
-        let fname = fname.to_owned();
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
         use std::os::unix::fs::OpenOptionsExt;
-        let th = std::thread::spawn(move || {
+        workers.spawn(i, move |worker| {
             let mut file = if cfg!(feature = "uring_direct") {
                 std::fs::OpenOptions::new()
                     .write(true)
                     .custom_flags(libc::O_DIRECT)
-                    .create(true)
                     .open(fname)?
             } else {
-                std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(fname)?
-            };
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
+                std::fs::OpenOptions::new().write(true).open(fname)?
             };
-            let bytes = num_chunks * chunk_size;
-            let slice = unsafe { std::slice::from_raw_parts(ptr, bytes as usize) };
-            let mut bufs = Vec::new();
-            for c in 0..num_chunks {
-                let i = c as usize * chunk_size as usize;
-                let s = &slice[i..i + chunk_size as usize];
-                bufs.push(std::io::IoSlice::new(s));
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
             }
-            let entries = num_chunks as u32;
-            let n = {
-                let mut io_uring = iou::IoUring::new(entries)?;
-                unsafe {
-                    let mut sq = io_uring.sq();
-                    let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Failed to prepare io_uring submission queue",
-                    ))?;
-                    sqe.prep_write_vectored(file.as_raw_fd(), &bufs, offset);
-                    io_uring.sq().submit()?;
+            let mut io_uring = iou::IoUring::new(1)?;
+            worker.start();
+            // one vectored write per run of consecutive chunks
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r);
+                let bytes = r.len();
+                let slice = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                let mut bufs = Vec::new();
+                for b in (0..bytes).step_by(chunk_size) {
+                    let e = (b + chunk_size).min(bytes);
+                    bufs.push(std::io::IoSlice::new(&slice[b..e]));
                 }
-
-                let mut cq = io_uring.cq();
-                let cqe = cq.wait_for_cqe()?;
-                cqe.result()? as usize
-            };
-            if n != (num_chunks * chunk_size) as usize {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("seq_write_uring_all: Failed to write data from io_uring queue, requested: {}, written: {}", chunk_size * num_chunks, n).as_str()
-                ));
+                let n = {
+                    unsafe {
+                        let mut sq = io_uring.sq();
+                        let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::other(
+                            "Failed to prepare io_uring submission queue",
+                        ))?;
+                        sqe.prep_write_vectored(file.as_raw_fd(), &bufs, offset);
+                        io_uring.sq().submit()?;
+                    }
+                    let mut cq = io_uring.cq();
+                    let cqe = cq.wait_for_cqe()?;
+                    cqe.result()? as usize
+                };
+                if n != bytes {
+                    return Err(std::io::Error::other(
+                        format!("par_write_uring_vec_all: Failed to write data from io_uring queue, requested: {}, written: {}", bytes, n).as_str()
+                    ));
+                }
+                digests.record(r.start as u64, slice);
             }
-
             file.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
-    join_and_check!(threads);
-    let e = t.elapsed();
-    Ok(e)
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
 
-
 //-----------------------------------------------------------------------------
 // @warning will normally fail for total size > (2GiB - 4kiB), limit imposed
 // by vectored i/o, so partial reads/writes must be handled
@@ -455,77 +653,319 @@ pub fn par_write_uring_all(
     num_chunks: u64,
     num_threads: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut threads = Vec::new();
-    let num_chunks_per_thread = (num_chunks + num_threads - 1) / num_threads;
-    let num_chunks_last_thread = num_chunks - num_chunks_per_thread * (num_threads - 1);
-    let t = Instant::now();
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    if cfg!(feature = "uring_direct") {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    }
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
     for i in 0..num_threads {
-        let offset = chunk_size * num_chunks_per_thread * i;
-        let mb = unsafe { Movable(filebuf.as_ptr().offset(offset as isize)) };
-        let num_chunks = if i != num_threads - 1 {
-            num_chunks_per_thread
-        } else {
-            num_chunks_last_thread
-        };
-        //@todo: use fallocate
-        {
-            std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&fname)?;
-        }
-        let fname = fname.to_owned();
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
         use std::os::unix::fs::OpenOptionsExt;
-        let th = std::thread::spawn(move || {
+        workers.spawn(i, move |worker| {
             let mut file = if cfg!(feature = "uring_direct") {
                 std::fs::OpenOptions::new()
                     .write(true)
                     .custom_flags(libc::O_DIRECT)
-                    .create(true)
                     .open(fname)?
             } else {
-                std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(fname)?
-            };
-            let ptr = match mb.get() {
-                None => return Err(IOError::new(IOErrorKind::Other, "NULL pointer")),
-                Some(p) => p,
+                std::fs::OpenOptions::new().write(true).open(fname)?
             };
-            let bytes = num_chunks * chunk_size;
-            let slice = unsafe { std::slice::from_raw_parts(ptr, bytes as usize) };
-            let entries = 1;
-            let n = {
-                let mut io_uring = iou::IoUring::new(entries)?;
-                unsafe {
-                    let mut sq = io_uring.sq();
-                    let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Failed to prepare io_uring submission queue",
-                    ))?;
-                    sqe.prep_write(file.as_raw_fd(), slice, offset);
-                    io_uring.sq().submit()?;
+            for r in chunks.placed(i) {
+                worker.place(unsafe { mb.slice(r) })?;
+            }
+            let mut io_uring = iou::IoUring::new(1)?;
+            worker.start();
+            // one write per run of consecutive chunks
+            for r in chunks.iter(i).runs() {
+                let offset = chunks.offset(i, &r);
+                let bytes = r.len();
+                let slice = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                let n = {
+                    unsafe {
+                        let mut sq = io_uring.sq();
+                        let mut sqe = sq.prepare_sqe().ok_or(std::io::Error::other(
+                            "Failed to prepare io_uring submission queue",
+                        ))?;
+                        sqe.prep_write(file.as_raw_fd(), slice, offset);
+                        io_uring.sq().submit()?;
+                    }
+                    let mut cq = io_uring.cq();
+                    let cqe = cq.wait_for_cqe()?;
+                    cqe.result()? as usize
+                };
+                if n != bytes {
+                    return Err(std::io::Error::other(
+                        format!("par_write_uring_all: Failed to write data from io_uring queue, requested: {}, written: {}", bytes, n).as_str()
+                    ));
                 }
-                let mut cq = io_uring.cq();
-                let cqe = cq.wait_for_cqe()?;
-                cqe.result()? as usize
-            };
-            if n != bytes as usize {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("seq_write_uring_all: Failed to write data from io_uring queue, requested: {}, written: {}", chunk_size * num_chunks, n).as_str()
-                ));
+                digests.record(r.start as u64, slice);
             }
             file.flush()?;
             Ok(())
         });
-        threads.push(th);
     }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// one glommio executor per worker, buffers are moved into glommio so the time
+// includes copying each chunk
+#[cfg(feature = "par_glommio_write")]
+pub fn par_glommio_write(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use glommio::{io::OpenOptions, LocalExecutor};
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let ex = LocalExecutor::default();
+            ex.run(async {
+                let file = OpenOptions::new().write(true).buffered_open(&fname).await?;
+                for r in chunks.placed(i) {
+                    worker.place(unsafe { mb.slice(r) })?;
+                }
+                worker.start();
+                for r in chunks.iter(i) {
+                    let offset = chunks.offset(i, &r);
+                    let buf = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                    file.write_at(buf.to_vec(), offset).await?;
+                    digests.record(r.start as u64, buf);
+                }
+                file.close().await?;
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
+}
+
+//-----------------------------------------------------------------------------
+// one glommio executor per worker, buffers are moved into glommio so the time
+// includes copying each chunk
+#[cfg(feature = "par_glommio_dma_write")]
+pub fn par_glommio_dma_write(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use glommio::{io::OpenOptions, LocalExecutor};
+    check_dio_align(fname, None, chunk_size, None)?;
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    create_files(fname, &chunks, false)?;
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        workers.spawn(i, move |worker| {
+            let ex = LocalExecutor::default();
+            ex.run(async {
+                let file = OpenOptions::new().write(true).dma_open(&fname).await?;
+                for r in chunks.placed(i) {
+                    worker.place(unsafe { mb.slice(r) })?;
+                }
+                worker.start();
+                for r in chunks.iter(i) {
+                    let offset = chunks.offset(i, &r);
+                    let mut buf = file.alloc_dma_buffer(r.len());
+                    let data = unsafe { mb.slice(chunks.buf(i, r.clone())) };
+                    buf.as_bytes_mut().copy_from_slice(data);
+                    file.write_at(buf, offset).await?;
+                    digests.record(r.start as u64, data);
+                }
+                file.close().await?;
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
+}
 
-    join_and_check!(threads);
+//-----------------------------------------------------------------------------
+// one current-thread runtime per worker with `queue_depth` tasks, each owning a
+// file handle since tokio files have no positional writes
+#[cfg(feature = "par_write_tokio_all")]
+pub fn par_write_tokio_all(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    create_files(fname, &chunks, false)?;
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = Arc::new(chunks.free_slots(i, depth)?);
+        workers.spawn(i, move |worker| {
+            let rt = tokio::runtime::Builder::new_current_thread().build()?;
+            rt.block_on(async {
+                let mut files = Vec::with_capacity(depth);
+                for _ in 0..depth {
+                    files.push(
+                        tokio::fs::OpenOptions::new()
+                            .write(true)
+                            .open(&fname)
+                            .await?,
+                    );
+                }
+                for r in chunks.placed(i) {
+                    worker.place(unsafe { mb.slice(r) })?;
+                }
+                worker.start();
+                let mut tasks = Vec::with_capacity(depth);
+                for (s, mut file) in files.into_iter().enumerate() {
+                    let mb = Movable(mb.0);
+                    let chunks = chunks.clone();
+                    let digests = digests.clone();
+                    let slots = slots.clone();
+                    tasks.push(tokio::spawn(async move {
+                        for r in chunks.iter(i).share(s, depth) {
+                            file.seek(SeekFrom::Start(chunks.offset(i, &r))).await?;
+                            let slot = slots.take(r.clone());
+                            let buf = unsafe { mb.slice(slot.clone()) };
+                            file.write_all(buf).await?;
+                            digests.record(r.start as u64, buf);
+                            slots.release(slot.start);
+                        }
+                        // wait for the last write to complete
+                        file.flush().await?;
+                        Ok::<(), std::io::Error>(())
+                    }));
+                }
+                for t in tasks {
+                    t.await??;
+                }
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
+}
 
-    let e = t.elapsed();
-    Ok(e)
+//-----------------------------------------------------------------------------
+// one tokio-uring runtime per worker with `queue_depth` tasks; the runtime owns
+// the buffers of the requests in flight, data is copied into them
+#[cfg(all(feature = "par_write_tokio_uring_all", target_os = "linux"))]
+pub fn par_write_tokio_uring_all(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    filebuf: &[u8],
+    cfg: &ParConfig,
+) -> std::io::Result<ParTiming> {
+    let fsize = chunk_size * num_chunks;
+    let chunks = Chunks::new(fsize, chunk_size, num_threads, cfg.variant, filebuf.len())?;
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_threads)?;
+    let depth = cfg.queue_depth;
+    create_files(fname, &chunks, false)?;
+    for i in 0..num_threads {
+        let mb = Movable(filebuf.as_ptr());
+        let chunks = chunks.clone();
+        let digests = digests.clone();
+        let fname = chunks.file_name(fname, i);
+        let slots = Arc::new(chunks.free_slots(i, depth)?);
+        workers.spawn(i, move |worker| {
+            tokio_uring::start(async {
+                let file = std::rc::Rc::new(
+                    tokio_uring::fs::OpenOptions::new()
+                        .write(true)
+                        .open(&fname)
+                        .await?,
+                );
+                for r in chunks.placed(i) {
+                    worker.place(unsafe { mb.slice(r) })?;
+                }
+                worker.start();
+                let mut tasks = Vec::with_capacity(depth);
+                for s in 0..depth {
+                    let file = file.clone();
+                    let mb = Movable(mb.0);
+                    let chunks = chunks.clone();
+                    let digests = digests.clone();
+                    let slots = slots.clone();
+                    tasks.push(tokio_uring::spawn(async move {
+                        let mut buf = Vec::with_capacity(chunk_size as usize);
+                        for r in chunks.iter(i).share(s, depth) {
+                            let offset = chunks.offset(i, &r);
+                            let bytes = r.len();
+                            buf.clear();
+                            let slot = slots.take(r.clone());
+                            let data = unsafe { mb.slice(slot.clone()) };
+                            buf.extend_from_slice(data);
+                            let (ret, b) = file.write_at(buf, offset).await;
+                            buf = b;
+                            let n = ret?;
+                            if n != bytes {
+                                return Err(std::io::Error::other(
+                                    format!("par_write_tokio_uring_all: Failed to write data, requested: {}, written: {}", bytes, n).as_str()
+                                ));
+                            }
+                            digests.record(r.start as u64, data);
+                            slots.release(slot.start);
+                        }
+                        Ok(())
+                    }));
+                }
+                for t in tasks {
+                    t.await??;
+                }
+                Ok(())
+            })
+        });
+    }
+    let mut timing = workers.join()?;
+    timing.digest = digests.chunked_digest();
+    truncate_files(fname, &chunks)?;
+    Ok(timing)
 }
@@ -0,0 +1,292 @@
+//! Read/compute pipeline: reader workers fill chunk buffers and hand them to
+//! compute workers through a bounded channel, the buffers go back to the
+//! readers once consumed. Measures how much of the i/o overlaps with the
+//! processing of the data.
+use crate::config::{ParConfig, PipeConfig, Source};
+use crate::consume::Consumer;
+use crate::digest::Digests;
+use crate::schedule::{data_size, Chunks};
+use crate::utility::{check_dio_align, map_file, off_t, pread_exact, Movable, RWF_NOWAIT};
+use crate::vec_io::read_vec_slice_offset_flags;
+use crate::workers::{Mode, ParTiming, Workers};
+use aligned_vec::*;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//-----------------------------------------------------------------------------
+/// Time the workers of one stage spent working and waiting for the other
+/// stage, summed over the workers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTiming {
+    pub threads: u64,
+    /// Reading, or computing.
+    pub busy: Duration,
+    /// Waiting for a free buffer, or for data.
+    pub stall: Duration,
+}
+
+impl StageTiming {
+    /// Fraction of the stage's time spent waiting.
+    pub fn stall_ratio(&self) -> f64 {
+        let t = (self.busy + self.stall).as_secs_f64();
+        if t > 0. {
+            self.stall.as_secs_f64() / t
+        } else {
+            0.
+        }
+    }
+}
+
+/// Timings of one pipeline run.
+#[derive(Clone, Debug)]
+pub struct PipeTiming {
+    /// All the workers, readers first.
+    pub timing: ParTiming,
+    pub read: StageTiming,
+    pub compute: StageTiming,
+}
+
+impl PipeTiming {
+    /// Readers waiting for buffers more than the compute workers wait for
+    /// data: the compute stage is the bottleneck.
+    pub fn cpu_bound(&self) -> bool {
+        self.read.stall_ratio() > self.compute.stall_ratio()
+    }
+}
+
+/// Print throughput and per-stage busy and stall times averaged over the
+/// workers of each stage; the stage waiting the least is the bottleneck.
+pub fn print_pipe_timing(name: &str, size_gib: f64, timing: &PipeTiming) {
+    let avg = |d: Duration, n: u64| d.as_secs_f64() / n.max(1) as f64;
+    let counters: String = timing
+        .timing
+        .counters
+        .iter()
+        .map(|(n, v)| format!(", {}: {}", n, v))
+        .collect();
+    println!(
+        "{:<32} {:.2} GiB/s, aggregate: {:.3} s, read: {:.3} s busy, {:.3} s stalled, compute: {:.3} s busy, {:.3} s stalled, {}{}",
+        format!("{}:", name),
+        size_gib / timing.timing.elapsed().as_secs_f64(),
+        timing.timing.elapsed().as_secs_f64(),
+        avg(timing.read.busy, timing.read.threads),
+        avg(timing.read.stall, timing.read.threads),
+        avg(timing.compute.busy, timing.compute.threads),
+        avg(timing.compute.stall, timing.compute.threads),
+        if timing.cpu_bound() {
+            "CPU-bound"
+        } else {
+            "I/O-bound"
+        },
+        counters
+    );
+    if let Some(d) = &timing.timing.digest {
+        println!("{:<32} {}", "  chunked digest:", d);
+    }
+}
+
+// busy and stall nanoseconds of the workers of one stage
+#[derive(Default)]
+struct StageClock {
+    busy: AtomicU64,
+    stall: AtomicU64,
+}
+
+impl StageClock {
+    fn add(&self, busy: Duration, stall: Duration) {
+        self.busy
+            .fetch_add(busy.as_nanos() as u64, Ordering::Relaxed);
+        self.stall
+            .fetch_add(stall.as_nanos() as u64, Ordering::Relaxed);
+    }
+    fn timing(&self, threads: u64) -> StageTiming {
+        StageTiming {
+            threads,
+            busy: Duration::from_nanos(self.busy.load(Ordering::Relaxed)),
+            stall: Duration::from_nanos(self.stall.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// workers are threads: buffers move between the stages through channels.
+// `num_readers` readers get the chunks as selected by the variant and read
+// them with `pcfg.source`, `num_computers` compute workers pass them to `consumer`
+pub fn pipeline<C: Consumer>(
+    fname: &str,
+    chunk_size: u64,
+    num_readers: u64,
+    num_computers: u64,
+    cfg: &ParConfig,
+    pcfg: &PipeConfig,
+    consumer: C,
+) -> std::io::Result<PipeTiming> {
+    let source = pcfg.source;
+    if cfg.variant.mode == Mode::Processes {
+        return Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            "The pipeline workers can only run as threads",
+        ));
+    }
+    let fsize = data_size(fname, cfg.variant.files, num_readers, chunk_size)?;
+    let chunks = Chunks::new(fsize, chunk_size, num_readers, cfg.variant, fsize as usize)?;
+    let mut num_buffers = pcfg.buffers.unwrap_or(2 * num_readers as usize);
+    if let Some(m) = cfg.mem_budget {
+        num_buffers = num_buffers.min((m / chunk_size) as usize);
+    }
+    if num_buffers == 0 {
+        return Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            format!(
+                "Memory budget too small for a chunk of {} bytes",
+                chunk_size
+            )
+            .as_str(),
+        ));
+    }
+    // the unaligned tail of the last chunk is read through a buffered descriptor
+    let align = if source == Source::Direct {
+        check_dio_align(&chunks.file_name(fname, 0), None, chunk_size, None)?.offset
+    } else {
+        1
+    };
+    // files mapped, and prefaulted, once before the workers start
+    let mut maps = Vec::new();
+    let mut prefault = Duration::ZERO;
+    if source == Source::Mmap {
+        for f in 0..chunks.num_files() {
+            let file = std::fs::File::open(chunks.file_name(fname, f))?;
+            let (mmap, t) = map_file(&file, &cfg.mmap)?;
+            maps.push(mmap);
+            prefault += t;
+        }
+    }
+    let (free_tx, free_rx) = sync_channel::<Vec<u8>>(num_buffers);
+    let (full_tx, full_rx) = sync_channel::<(u64, usize, Vec<u8>)>(num_buffers);
+    for _ in 0..num_buffers {
+        let buf: Vec<u8> =
+            page_aligned_vec(chunk_size as usize, chunk_size as usize, Some(0), false);
+        free_tx.send(buf).unwrap();
+    }
+    let free_rx = Arc::new(Mutex::new(free_rx));
+    let full_rx = Arc::new(Mutex::new(full_rx));
+    let read_clock = Arc::new(StageClock::default());
+    let compute_clock = Arc::new(StageClock::default());
+    // chunks are hashed by the compute workers, before the consumer
+    let digests = Arc::new(Digests::new(cfg.hash, fsize, chunk_size)?);
+    let mut workers = Workers::new(cfg, num_readers + num_computers)?;
+    let eagain = Arc::new(AtomicU64::new(0));
+    for i in 0..num_readers {
+        let chunks = chunks.clone();
+        let fname = chunks.file_name(fname, i);
+        let map = maps.get(if maps.len() > 1 { i as usize } else { 0 });
+        let src = Movable(map.map_or(std::ptr::null(), |m| m.as_ptr()));
+        let free = free_rx.clone();
+        let full = full_tx.clone();
+        let clock = read_clock.clone();
+        let flags = cfg.rwf;
+        let eagain = eagain.clone();
+        workers.spawn(i, move |worker| {
+            let file = if source == Source::Direct {
+                std::fs::OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_DIRECT)
+                    .open(&fname)?
+            } else {
+                std::fs::File::open(&fname)?
+            };
+            let tail_file = std::fs::File::open(&fname)?;
+            let ended = || IOError::other("Compute stage ended early");
+            let mut busy = Duration::ZERO;
+            let mut stall = Duration::ZERO;
+            worker.start();
+            for r in chunks.iter(i) {
+                let t = Instant::now();
+                let mut buf = free.lock().unwrap().recv().map_err(|_| ended())?;
+                let t1 = Instant::now();
+                stall += t1 - t;
+                let len = r.len();
+                let offset = chunks.offset(i, &r);
+                match source {
+                    Source::Pread | Source::Direct => {
+                        let fd = if (len as u64).is_multiple_of(align) {
+                            file.as_raw_fd()
+                        } else {
+                            tail_file.as_raw_fd()
+                        };
+                        pread_exact(fd, &mut buf[..len], offset)?
+                    }
+                    Source::Mmap => {
+                        let o = offset as usize;
+                        buf[..len].copy_from_slice(unsafe { src.slice(o..o + len) });
+                    }
+                    Source::Preadv2 => {
+                        let n = read_vec_slice_offset_flags(
+                            &file,
+                            &mut buf[..len],
+                            chunk_size,
+                            offset as off_t,
+                            flags,
+                        )?;
+                        eagain.fetch_add(n, Ordering::Relaxed);
+                    }
+                }
+                busy += t1.elapsed();
+                full.send((r.start as u64, len, buf)).map_err(|_| ended())?;
+            }
+            clock.add(busy, stall);
+            Ok(())
+        });
+    }
+    for j in 0..num_computers {
+        let free = free_tx.clone();
+        let full = full_rx.clone();
+        let clock = compute_clock.clone();
+        let mut consumer = consumer.clone();
+        let digests = digests.clone();
+        workers.spawn(num_readers + j, move |worker| {
+            let mut busy = Duration::ZERO;
+            let mut stall = Duration::ZERO;
+            worker.start();
+            loop {
+                let t = Instant::now();
+                let msg = full.lock().unwrap().recv();
+                let t1 = Instant::now();
+                stall += t1 - t;
+                // all the readers are done
+                let Ok((offset, len, buf)) = msg else { break };
+                digests.record(offset, &buf[..len]);
+                consumer(offset, &buf[..len])?;
+                busy += t1.elapsed();
+                // fails once the readers are done
+                let _ = free.send(buf);
+            }
+            clock.add(busy, stall);
+            Ok(())
+        });
+    }
+    // the stages only see each other's end once these are dropped
+    drop(free_tx);
+    drop(full_tx);
+    let mut timing = workers.join()?;
+    if source == Source::Mmap {
+        timing.prefault = Some(prefault);
+    }
+    timing.digest = digests.chunked_digest();
+    if source == Source::Preadv2 && cfg.rwf & RWF_NOWAIT != 0 {
+        timing
+            .counters
+            .push(("EAGAIN", eagain.load(Ordering::Relaxed)));
+    }
+    Ok(PipeTiming {
+        timing,
+        read: read_clock.timing(num_readers),
+        compute: compute_clock.timing(num_computers),
+    })
+}
@@ -0,0 +1,69 @@
+//! Persistent worker threads reused across runs so that thread creation does not
+//! end up in the measurements.
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+//-----------------------------------------------------------------------------
+/// Job `i` always runs on pool thread `i`, so a worker keeps its thread, and its
+/// cpu pinning, across runs. The pool grows on demand and is never shrunk.
+pub struct ThreadPool {
+    threads: Mutex<Vec<(Sender<Job>, JoinHandle<()>)>>,
+}
+
+impl Default for ThreadPool {
+    fn default() -> Self {
+        ThreadPool::new(0)
+    }
+}
+
+impl ThreadPool {
+    pub fn new(num_threads: usize) -> Self {
+        let pool = ThreadPool {
+            threads: Mutex::new(Vec::new()),
+        };
+        pool.reserve(num_threads);
+        pool
+    }
+
+    pub fn size(&self) -> usize {
+        self.threads.lock().unwrap().len()
+    }
+
+    /// Make sure at least `num_threads` threads are available.
+    pub fn reserve(&self, num_threads: usize) {
+        let mut threads = self.threads.lock().unwrap();
+        while threads.len() < num_threads {
+            let (tx, rx) = channel::<Job>();
+            let th = std::thread::spawn(move || {
+                for job in rx {
+                    job();
+                }
+            });
+            threads.push((tx, th));
+        }
+    }
+
+    /// Run `job` on thread `index`, growing the pool if needed.
+    pub fn execute<F>(&self, index: usize, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.reserve(index + 1);
+        let threads = self.threads.lock().unwrap();
+        // the receiving thread only exits when the pool is dropped
+        threads[index].0.send(Box::new(job)).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        for (tx, th) in threads {
+            drop(tx);
+            let _ = th.join();
+        }
+    }
+}
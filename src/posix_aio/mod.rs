@@ -0,0 +1,121 @@
+//! POSIX asynchronous I/O: `aio_read`/`aio_write` completed with `aio_suspend`.
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::os::unix::io::RawFd;
+
+//-----------------------------------------------------------------------------
+/// Run `opcode`, `libc::LIO_READ` or `libc::LIO_WRITE`, on every
+/// `(buffer, length, offset)` request, keeping up to `depth` control blocks in
+/// flight; a short transfer is an error. `complete` is called with each request
+/// once it is done.
+///
+/// # Safety
+/// The buffers must be valid for the whole call.
+pub unsafe fn run<I, F>(
+    fd: RawFd,
+    opcode: i32,
+    depth: usize,
+    mut reqs: I,
+    mut complete: F,
+) -> std::io::Result<()>
+where
+    I: Iterator<Item = (*mut u8, usize, u64)>,
+    F: FnMut(*mut u8, usize, u64),
+{
+    let mut cbs: Vec<libc::aiocb> = (0..depth).map(|_| std::mem::zeroed()).collect();
+    let mut free: Vec<usize> = (0..depth).rev().collect();
+    let mut in_flight: Vec<usize> = Vec::with_capacity(depth);
+    let mut list: Vec<*const libc::aiocb> = Vec::with_capacity(depth);
+    let mut pending = None;
+    let mut done = false;
+    let ret = 'run: loop {
+        while !done && !free.is_empty() {
+            match pending.take().or_else(|| reqs.next()) {
+                Some((buf, len, offset)) => {
+                    let k = free.pop().unwrap();
+                    let cb = &mut cbs[k];
+                    *cb = std::mem::zeroed();
+                    cb.aio_fildes = fd;
+                    cb.aio_buf = buf as *mut libc::c_void;
+                    cb.aio_nbytes = len;
+                    cb.aio_offset = offset as libc::off_t;
+                    cb.aio_sigevent.sigev_notify = libc::SIGEV_NONE;
+                    let r = if opcode == libc::LIO_WRITE {
+                        libc::aio_write(cb)
+                    } else {
+                        libc::aio_read(cb)
+                    };
+                    if r != 0 {
+                        let err = IOError::last_os_error();
+                        free.push(k);
+                        // out of resources: retry once some requests complete
+                        if err.kind() == IOErrorKind::WouldBlock && !in_flight.is_empty() {
+                            pending = Some((buf, len, offset));
+                            break;
+                        }
+                        break 'run Err(err);
+                    }
+                    in_flight.push(k);
+                }
+                None => done = true,
+            }
+        }
+        if in_flight.is_empty() {
+            break Ok(());
+        }
+        list.clear();
+        list.extend(in_flight.iter().map(|&k| &cbs[k] as *const libc::aiocb));
+        if libc::aio_suspend(list.as_ptr(), list.len() as i32, std::ptr::null()) != 0 {
+            let err = IOError::last_os_error();
+            if err.kind() == IOErrorKind::Interrupted {
+                continue;
+            }
+            break Err(err);
+        }
+        let mut err = None;
+        in_flight.retain(|&k| match libc::aio_error(&cbs[k]) {
+            libc::EINPROGRESS => true,
+            0 => {
+                let n = libc::aio_return(&mut cbs[k]);
+                if n as usize != cbs[k].aio_nbytes {
+                    err = Some(IOError::other(
+                        format!(
+                            "POSIX AIO: Failed to transfer data, requested: {}, transferred: {}",
+                            cbs[k].aio_nbytes, n
+                        )
+                        .as_str(),
+                    ));
+                } else {
+                    complete(
+                        cbs[k].aio_buf as *mut u8,
+                        cbs[k].aio_nbytes,
+                        cbs[k].aio_offset as u64,
+                    );
+                }
+                free.push(k);
+                false
+            }
+            e => {
+                libc::aio_return(&mut cbs[k]);
+                err = Some(IOError::from_raw_os_error(e));
+                free.push(k);
+                false
+            }
+        });
+        if let Some(e) = err {
+            break Err(e);
+        }
+    };
+    // the control blocks must outlive the requests still in flight after an error
+    if !in_flight.is_empty() {
+        libc::aio_cancel(fd, std::ptr::null_mut());
+        for &k in &in_flight {
+            let cb = &cbs[k] as *const libc::aiocb;
+            while libc::aio_error(cb) == libc::EINPROGRESS {
+                libc::aio_suspend(&cb, 1, std::ptr::null());
+            }
+            libc::aio_return(&mut cbs[k]);
+        }
+    }
+    ret
+}
@@ -1,16 +1,23 @@
 //! Read from file using a variety of APIs.
 #[cfg(any(feature = "async_glommio_read", feature = "seq_glommio_read"))]
-use glommio::{io::BufferedFile, LocalExecutor};
+use glommio::io::BufferedFile;
+#[cfg(any(
+    feature = "async_glommio_read",
+    feature = "seq_glommio_read",
+    feature = "seq_glommio_dma_read"
+))]
+use glommio::LocalExecutor;
 
-use crate::utility::dump;
-use aligned_vec::*;
-use memmap2::MmapOptions;
+use crate::config::MmapConfig;
+use crate::digest::Digests;
+use crate::utility::{check_dio_align, map_file, pread_exact, MmapTiming, Ring};
 use std::io::Read;
+use std::os::unix::io::AsRawFd;
 use std::time::{Duration, Instant};
 use std::{fs::OpenOptions, os::unix::fs::OpenOptionsExt};
 
 //-----------------------------------------------------------------------------
-pub fn seq_read(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
+pub fn seq_read(fname: &str, chunk_size: u64, digests: &Digests) -> std::io::Result<Duration> {
     let fsize = std::fs::metadata(fname)?.len();
     let mut r = 0_u64;
     let mut file = std::fs::File::open(fname)?;
@@ -21,26 +28,37 @@ pub fn seq_read(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
             buf.set_len(chunk_size.min(fsize - r) as usize);
         }
         file.read_exact(&mut buf)?;
+        digests.record(r, &buf);
         r += chunk_size;
     }
     let e = t.elapsed();
-    dump(&buf)?;
+    digests.dump_unhashed(&buf)?;
     Ok(e)
 }
 //-----------------------------------------------------------------------------
-pub fn seq_read_all(fname: &str, chunk_size: u64, filebuf: &mut [u8]) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
+pub fn seq_read_all(
+    fname: &str,
+    chunk_size: u64,
+    filebuf: &mut [u8],
+    digests: &Digests,
+) -> std::io::Result<Duration> {
+    let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let mut r = 0_u64;
     let mut file = std::fs::File::open(fname)?;
     //let mut filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
     let t = Instant::now();
     while r < fsize {
         let b = r as usize;
-        let e = (b + (chunk_size as usize)).min(fsize as usize);
-        r += file.read(&mut filebuf[b..e])? as u64;
+        let e = ((b / chunk_size as usize + 1) * chunk_size as usize).min(fsize as usize);
+        r += file.read(&mut filebuf[ring.slot(b..e)])? as u64;
+        if r as usize == e {
+            let c = b / chunk_size as usize * chunk_size as usize;
+            digests.record(c as u64, &filebuf[ring.slot(c..e)]);
+        }
     }
     let e = t.elapsed();
-    dump(filebuf)?;
+    digests.dump_unhashed(filebuf)?;
     Ok(e)
 }
 //-----------------------------------------------------------------------------
@@ -48,33 +66,46 @@ pub fn seq_read_direct_all(
     fname: &str,
     chunk_size: u64,
     filebuf: &mut [u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
-    if chunk_size % 512 != 0 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "O_DIRECT requires a chunk size multiple of 512'",
-        ));
-    }
-    let fsize = filebuf.len() as u64;
+    let align = check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
+    // the unaligned tail is read through a separate buffered descriptor
+    let direct_end = fsize / align.offset * align.offset;
     let mut r = 0_u64;
     let mut file = OpenOptions::new()
         .read(true)
         .custom_flags(libc::O_DIRECT)
         .open(fname)?;
-    let mut filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
+    let tail_file = std::fs::File::open(fname)?;
     let t = Instant::now();
-    while r < fsize {
+    while r < direct_end {
         let b = r as usize;
-        let e = (b + (chunk_size as usize)).min(fsize as usize);
-        r += file.read(&mut filebuf[b..e])? as u64;
+        let e = ((b / chunk_size as usize + 1) * chunk_size as usize).min(direct_end as usize);
+        r += file.read(&mut filebuf[ring.slot(b..e)])? as u64;
+        // the chunk holding `direct_end` is hashed after reading the tail
+        if r as usize == e && (e.is_multiple_of(chunk_size as usize) || r == fsize) {
+            let c = b / chunk_size as usize * chunk_size as usize;
+            digests.record(c as u64, &filebuf[ring.slot(c..e)]);
+        }
+    }
+    if direct_end < fsize {
+        pread_exact(
+            tail_file.as_raw_fd(),
+            &mut filebuf[ring.slot(direct_end as usize..fsize as usize)],
+            direct_end,
+        )?;
+        let c = (direct_end / chunk_size * chunk_size) as usize;
+        digests.record(c as u64, &filebuf[ring.slot(c..fsize as usize)]);
     }
     let e = t.elapsed();
-    dump(&filebuf)?;
+    digests.dump_unhashed(filebuf)?;
     Ok(e)
 }
 
 //-----------------------------------------------------------------------------
-pub fn seq_read_buf(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
+pub fn seq_read_buf(fname: &str, chunk_size: u64, digests: &Digests) -> std::io::Result<Duration> {
     let fsize = std::fs::metadata(fname)?.len();
     let mut r = 0_u64;
     let file = std::fs::File::open(fname)?;
@@ -86,10 +117,11 @@ pub fn seq_read_buf(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
             buf.set_len(chunk_size.min(fsize - r) as usize);
         }
         br.read_exact(&mut buf)?;
+        digests.record(r, &buf);
         r += chunk_size;
     }
     let e = t.elapsed();
-    dump(&buf)?;
+    digests.dump_unhashed(&buf)?;
     Ok(e)
 }
 //-----------------------------------------------------------------------------
@@ -97,28 +129,39 @@ pub fn seq_read_buf_all(
     fname: &str,
     chunk_size: u64,
     filebuf: &mut [u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
+    let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let mut r = 0_u64;
     let file = std::fs::File::open(fname)?;
     let mut br = std::io::BufReader::new(file);
     let t = Instant::now();
     while r < fsize {
         let b = r as usize;
-        let e = (b + (chunk_size as usize)).min(fsize as usize);
-        r += br.read(&mut filebuf[b..e])? as u64;
+        let e = ((b / chunk_size as usize + 1) * chunk_size as usize).min(fsize as usize);
+        r += br.read(&mut filebuf[ring.slot(b..e)])? as u64;
+        if r as usize == e {
+            let c = b / chunk_size as usize * chunk_size as usize;
+            digests.record(c as u64, &filebuf[ring.slot(c..e)]);
+        }
     }
     let e = t.elapsed();
-    dump(filebuf)?;
+    digests.dump_unhashed(filebuf)?;
     Ok(e)
 }
 //-----------------------------------------------------------------------------
-pub fn seq_read_mmap(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
+pub fn seq_read_mmap(
+    fname: &str,
+    chunk_size: u64,
+    cfg: &MmapConfig,
+    digests: &Digests,
+) -> std::io::Result<MmapTiming> {
     let fsize = std::fs::metadata(fname)?.len();
     let mut r = 0_u64;
     let file = std::fs::File::open(fname)?;
     let mut buf = vec![0_u8; chunk_size as usize];
-    let mmap = unsafe { MmapOptions::new().map(&file)? };
+    let (mmap, prefault) = map_file(&file, cfg)?;
     let t = Instant::now();
     while r < fsize {
         unsafe {
@@ -127,89 +170,168 @@ pub fn seq_read_mmap(fname: &str, chunk_size: u64) -> std::io::Result<Duration>
         let b = r as usize;
         let e = b + buf.len();
         buf.copy_from_slice(&mmap[b..e]);
+        digests.record(r, &buf);
         r += chunk_size;
     }
-    let e = t.elapsed();
-    dump(&buf)?;
-    Ok(e)
+    let copy = t.elapsed();
+    digests.dump_unhashed(&buf)?;
+    Ok(MmapTiming {
+        prefault,
+        copy,
+        writeback: Duration::ZERO,
+    })
 }
 //-----------------------------------------------------------------------------
 pub fn seq_read_mmap_all(
     fname: &str,
     chunk_size: u64,
     filebuf: &mut [u8],
-) -> std::io::Result<Duration> {
-    let fsize = filebuf.len() as u64;
+    cfg: &MmapConfig,
+    digests: &Digests,
+) -> std::io::Result<MmapTiming> {
+    let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let file = std::fs::File::open(fname)?;
-    let mmap = unsafe { MmapOptions::new().map(&file)? };
+    let (mmap, prefault) = map_file(&file, cfg)?;
     let mut r = 0_u64;
     let t = Instant::now();
     while r < fsize {
         let b = r as usize;
         let e = (b + (chunk_size as usize)).min(fsize as usize);
-        filebuf[b..e].copy_from_slice(&mmap[b..e]);
+        filebuf[ring.slot(b..e)].copy_from_slice(&mmap[b..e]);
+        digests.record(r, &filebuf[ring.slot(b..e)]);
         r += chunk_size;
     }
-    let e = t.elapsed();
-    dump(filebuf)?;
-    Ok(e)
+    let copy = t.elapsed();
+    digests.dump_unhashed(filebuf)?;
+    Ok(MmapTiming {
+        prefault,
+        copy,
+        writeback: Duration::ZERO,
+    })
 }
 //-----------------------------------------------------------------------------
 pub fn seq_read_vec_all(
     fname: &str,
     chunk_size: u64,
     filebuf: &mut [u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
+    let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let mut file = std::fs::File::open(fname)?;
+    let mut r = 0_u64;
     let t = Instant::now();
     use crate::vec_io;
-    vec_io::read_vec_slice(&mut file, filebuf, chunk_size)?;
+    // one vectored read per pass over the ring
+    while r < fsize {
+        let len = (ring.capacity() as u64).min(fsize - r);
+        vec_io::read_vec_slice(&mut file, &mut filebuf[..len as usize], chunk_size)?;
+        digests.record(r, &filebuf[..len as usize]);
+        r += len;
+    }
     let e = t.elapsed();
     Ok(e)
 }
 //-----------------------------------------------------------------------------
 #[cfg(feature = "seq_glommio_read")]
-pub fn seq_glommio_read(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
+pub fn seq_glommio_read(
+    fname: &str,
+    chunk_size: u64,
+    filebuf: &mut [u8],
+    digests: &Digests,
+) -> std::io::Result<Duration> {
     let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let ex = LocalExecutor::default();
     ex.run(async {
         let mut r = 0_u64;
-        let mut filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
         let file = BufferedFile::open(fname).await?;
         let t = Instant::now();
         while r < fsize {
             let b = r as usize;
             let e = (fsize as usize).min(b + chunk_size as usize);
-            filebuf[b..e].copy_from_slice(&file.read_at(b as u64, chunk_size as usize).await?);
+            filebuf[ring.slot(b..e)].copy_from_slice(&file.read_at(b as u64, e - b).await?);
+            digests.record(r, &filebuf[ring.slot(b..e)]);
             r += chunk_size;
         }
         let e = t.elapsed();
-        dump(&filebuf)?;
+        file.close().await?;
+        digests.dump_unhashed(filebuf)?;
         Ok(e)
     })
 }
 //-----------------------------------------------------------------------------
 #[cfg(feature = "async_glommio_read")]
-pub fn async_glommio_read(fname: &str, chunk_size: u64) -> std::io::Result<Duration> {
+pub fn async_glommio_read(
+    fname: &str,
+    chunk_size: u64,
+    filebuf: &mut [u8],
+    digests: &Digests,
+) -> std::io::Result<Duration> {
     let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let ex = LocalExecutor::default();
     ex.run(async {
         let mut r = 0_u64;
-        let mut filebuf: Vec<u8> = page_aligned_vec(fsize as usize, fsize as usize, Some(0), false);
-        let file = BufferedFile::open(fname).await?;
+        let file = std::rc::Rc::new(BufferedFile::open(fname).await?);
+        let t = Instant::now();
+        // one task per chunk, as many reads in flight as the ring has slots;
+        // the oldest is copied to its slot before the next one is issued
+        let slots = ring.capacity().div_ceil(chunk_size as usize).max(1);
+        let mut f = std::collections::VecDeque::with_capacity(slots);
+        loop {
+            if r < fsize && f.len() < slots {
+                let b = r as usize;
+                let e = (fsize as usize).min(b + chunk_size as usize);
+                let file = file.clone();
+                f.push_back((
+                    b,
+                    e,
+                    glommio::spawn_local(async move { file.read_at(b as u64, e - b).await }),
+                ));
+                r += chunk_size;
+                continue;
+            }
+            let Some(i) = f.pop_front() else {
+                break;
+            };
+            filebuf[ring.slot(i.0..i.1)].copy_from_slice(&i.2.await?);
+            digests.record(i.0 as u64, &filebuf[ring.slot(i.0..i.1)]);
+        }
+        let e = t.elapsed();
+        digests.dump_unhashed(filebuf)?;
+        Ok(e)
+    })
+}
+//-----------------------------------------------------------------------------
+#[cfg(feature = "seq_glommio_dma_read")]
+pub fn seq_glommio_dma_read(
+    fname: &str,
+    chunk_size: u64,
+    filebuf: &mut [u8],
+    digests: &Digests,
+) -> std::io::Result<Duration> {
+    use glommio::io::DmaFile;
+    let fsize = std::fs::metadata(fname)?.len();
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
+    // `read_at` aligns the unaligned tail internally
+    check_dio_align(fname, None, chunk_size, None)?;
+    let ex = LocalExecutor::default();
+    ex.run(async {
+        let mut r = 0_u64;
+        let file = DmaFile::open(fname).await?;
         let t = Instant::now();
-        let mut f = Vec::with_capacity(((fsize + chunk_size) / chunk_size) as usize);
         while r < fsize {
             let b = r as usize;
             let e = (fsize as usize).min(b + chunk_size as usize);
-            f.push((b, e, file.read_at(b as u64, chunk_size as usize).await));
+            filebuf[ring.slot(b..e)].copy_from_slice(&file.read_at(b as u64, e - b).await?);
+            digests.record(r, &filebuf[ring.slot(b..e)]);
             r += chunk_size;
         }
-        for i in f {
-            filebuf[i.0..i.1].copy_from_slice(&i.2?);
-        }
         let e = t.elapsed();
-        dump(&filebuf)?;
+        file.close().await?;
+        digests.dump_unhashed(filebuf)?;
         Ok(e)
     })
 }
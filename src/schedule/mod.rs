@@ -0,0 +1,697 @@
+//! Distribution of the chunks of a file among the workers.
+use crate::config::Variant;
+use crate::utility::{file_size, option_enum, SharedCounter};
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+//-----------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Schedule {
+    /// Each worker gets a fixed, contiguous block of `num_chunks / num_threads`
+    /// chunks.
+    Static,
+    /// Workers pull the index of the next chunk from a shared atomic counter
+    /// until all the chunks are taken.
+    Dynamic,
+}
+
+option_enum!(Schedule, "schedule", {
+    "static" => Static,
+    "dynamic" => Dynamic,
+});
+
+//-----------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Partition {
+    /// Worker `i` gets chunks `[i*span, (i+1)*span)`, segmented layout.
+    Contiguous,
+    /// Worker `i` gets chunks `i, i+N, i+2N, ...`, round-robin layout.
+    Strided,
+    /// Contiguous blocks of a fixed random permutation of the chunks, each
+    /// worker accesses the file at random offsets.
+    RandomPermutation,
+}
+
+option_enum!(Partition, "partition", {
+    "contiguous" => Contiguous,
+    "strided" => Strided,
+    "random-permutation" => RandomPermutation,
+});
+
+//-----------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Files {
+    /// All the workers access the same file (N-to-1).
+    Shared,
+    /// Worker `i` accesses its own file `<fname>.<i>`, holding its contiguous
+    /// block of chunks (N-to-N).
+    PerThread,
+}
+
+option_enum!(Files, "file layout", {
+    "shared" => Shared,
+    "per-thread" => PerThread,
+});
+
+/// Size of the data in `fname` or, with one file per thread, in the files
+/// `fname.0` to `fname.<num_threads - 1>`; each of those must hold the block
+/// of chunks `Chunks` assigns to its worker, all of the same size but the last.
+/// Files left by runs with more threads are ignored.
+pub fn data_size(
+    fname: &str,
+    files: Files,
+    num_threads: u64,
+    chunk_size: u64,
+) -> std::io::Result<u64> {
+    if files == Files::Shared {
+        return file_size(fname);
+    }
+    let mut sizes = Vec::new();
+    for i in 0..num_threads {
+        let name = format!("{}.{}", fname, i);
+        sizes.push(file_size(&name).map_err(|e| {
+            IOError::new(
+                e.kind(),
+                format!(
+                    "Per-thread file '{}' of {} threads: {}",
+                    name, num_threads, e
+                )
+                .as_str(),
+            )
+        })?);
+    }
+    let size: u64 = sizes.iter().sum();
+    let block = size.div_ceil(chunk_size).div_ceil(num_threads) * chunk_size;
+    for (i, s) in sizes.iter().enumerate() {
+        let expected = size.saturating_sub(i as u64 * block).min(block);
+        if *s != expected {
+            return Err(IOError::new(
+                IOErrorKind::InvalidInput,
+                format!(
+                    "Per-thread file '{}.{}' holds {} bytes, {} threads with chunks of {} bytes need {}",
+                    fname, i, s, num_threads, chunk_size, expected
+                )
+                .as_str(),
+            ));
+        }
+    }
+    Ok(size)
+}
+
+/// Fisher-Yates shuffle of `0..n` driven by splitmix64, the seed is fixed so
+/// that every run and every method sees the same access order.
+fn permutation(n: u64) -> Vec<u64> {
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    let mut v: Vec<u64> = (0..n).collect();
+    for i in (1..v.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        v.swap(i, j);
+    }
+    v
+}
+
+//-----------------------------------------------------------------------------
+/// Chunks of `size` bytes split among `num_threads` workers; the last chunk is
+/// shorter when `size` is not a multiple of `chunk_size`.
+///
+/// With one file per thread each file holds a worker's contiguous block, only
+/// the static schedule and the contiguous partition apply since no worker can
+/// touch another worker's file.
+///
+/// When the i/o buffer is smaller than the data set the chunks are streamed:
+/// each worker reuses a ring of chunk buffers, as many as fit in its share of
+/// the buffer, and consecutive chunks are not merged into runs. Engines keeping
+/// several requests in flight take their slots from `free_slots`.
+pub struct Chunks {
+    size: u64,
+    chunk_size: u64,
+    num_chunks: u64,
+    num_threads: u64,
+    schedule: Schedule,
+    partition: Partition,
+    files: Files,
+    /// Chunk order, only used with a random permutation.
+    order: Vec<u64>,
+    next: SharedCounter,
+    /// Chunk buffers in each worker's ring, 0 when the buffer holds all the data.
+    slots: u64,
+}
+
+impl Chunks {
+    pub fn new(
+        size: u64,
+        chunk_size: u64,
+        num_threads: u64,
+        variant: Variant,
+        buf_len: usize,
+    ) -> std::io::Result<Arc<Self>> {
+        let Variant {
+            schedule,
+            partition,
+            files,
+            ..
+        } = variant;
+        if files == Files::PerThread
+            && (schedule != Schedule::Static || partition != Partition::Contiguous)
+        {
+            return Err(IOError::new(
+                IOErrorKind::InvalidInput,
+                format!(
+                    "Per-thread files need a static, contiguous split, not {}, {}",
+                    schedule, partition
+                )
+                .as_str(),
+            ));
+        }
+        let num_chunks = size.div_ceil(chunk_size);
+        let slots = if buf_len as u64 >= size {
+            0
+        } else {
+            match buf_len as u64 / (num_threads * chunk_size) {
+                0 => {
+                    return Err(IOError::new(
+                        IOErrorKind::InvalidInput,
+                        format!(
+                            "Buffer of {} bytes too small for {} chunk(s) of {} bytes",
+                            buf_len, num_threads, chunk_size
+                        )
+                        .as_str(),
+                    ))
+                }
+                n => n,
+            }
+        };
+        let order = if partition == Partition::RandomPermutation {
+            permutation(num_chunks)
+        } else {
+            Vec::new()
+        };
+        Ok(Arc::new(Chunks {
+            size,
+            chunk_size,
+            num_chunks,
+            num_threads,
+            schedule,
+            partition,
+            files,
+            order,
+            next: SharedCounter::new(),
+            slots,
+        }))
+    }
+
+    /// Byte range of chunk `c`.
+    pub fn chunk(&self, c: u64) -> Range<usize> {
+        let b = c * self.chunk_size;
+        let e = (b + self.chunk_size).min(self.size);
+        b as usize..e as usize
+    }
+
+    /// Range of the i/o buffer holding the chunks at `r` accessed by worker
+    /// `i`: `r` itself or, when streaming, a slot of the worker's ring.
+    pub fn buf(&self, i: u64, r: Range<usize>) -> Range<usize> {
+        if self.slots == 0 {
+            return r;
+        }
+        // one request at a time: any slot of the worker's ring will do, those
+        // of a static assignment are used in turn
+        let c = r.start as u64 / self.chunk_size;
+        let stride = if self.partition == Partition::Strided {
+            self.num_threads
+        } else {
+            1
+        };
+        let b = ((i * self.slots + (c / stride) % self.slots) * self.chunk_size) as usize;
+        b..b + r.len()
+    }
+
+    /// Slots of worker `i` for `depth` requests in flight; when streaming the
+    /// ring must hold at least `depth` chunks.
+    pub fn free_slots(&self, i: u64, depth: usize) -> std::io::Result<Slots> {
+        if self.slots == 0 {
+            return Ok(Slots {
+                base: 0,
+                chunk_size: 0,
+                free: None,
+            });
+        }
+        if self.slots < depth as u64 {
+            return Err(IOError::new(
+                IOErrorKind::InvalidInput,
+                format!(
+                    "Buffer holds {} chunk(s) per worker, fewer than the queue depth {}",
+                    self.slots, depth
+                )
+                .as_str(),
+            ));
+        }
+        Ok(Slots {
+            base: (i * self.slots * self.chunk_size) as usize,
+            chunk_size: self.chunk_size as usize,
+            free: Some(Mutex::new((0..self.slots as usize).rev().collect())),
+        })
+    }
+
+    /// Ranges of the i/o buffer worker `i` writes to or reads from, to place
+    /// buffers. With a dynamic schedule no chunk is owned: the whole buffer is
+    /// interleaved, chunk by chunk, among the workers.
+    pub fn placed(&self, i: u64) -> Vec<Range<usize>> {
+        if self.slots == 0 && self.schedule == Schedule::Dynamic {
+            return (i..self.num_chunks)
+                .step_by(self.num_threads as usize)
+                .map(|c| self.chunk(c))
+                .collect();
+        }
+        if self.slots == 0 {
+            return self.owned(i).runs().collect();
+        }
+        let ring = (self.slots * self.chunk_size) as usize;
+        std::iter::once(i as usize * ring..(i as usize + 1) * ring).collect()
+    }
+
+    /// Number of files the chunks are stored in.
+    pub fn num_files(&self) -> u64 {
+        match self.files {
+            Files::Shared => 1,
+            Files::PerThread => self.num_threads,
+        }
+    }
+
+    /// File accessed by worker `i`.
+    pub fn file_name(&self, fname: &str, i: u64) -> String {
+        match self.files {
+            Files::Shared => fname.to_owned(),
+            Files::PerThread => format!("{}.{}", fname, i),
+        }
+    }
+
+    /// Size of the file accessed by worker `i`.
+    pub fn file_size(&self, i: u64) -> u64 {
+        match self.files {
+            Files::Shared => self.size,
+            Files::PerThread => self.owned(i).map(|r| r.len() as u64).sum(),
+        }
+    }
+
+    /// Offset in the file of worker `i` of the chunks at `r`.
+    pub fn offset(&self, i: u64, r: &Range<usize>) -> u64 {
+        match self.files {
+            Files::Shared => r.start as u64,
+            Files::PerThread => r.start as u64 - i * self.per_thread() * self.chunk_size,
+        }
+    }
+
+    /// Offset in the data of offset `offset` in the file of worker `i`.
+    pub fn data_offset(&self, i: u64, offset: u64) -> u64 {
+        match self.files {
+            Files::Shared => offset,
+            Files::PerThread => offset + i * self.per_thread() * self.chunk_size,
+        }
+    }
+
+    /// Chunks processed by worker `i`; with a dynamic schedule every call to
+    /// `next` claims a chunk from the shared counter, in file order or in the
+    /// order of the random permutation.
+    pub fn iter(&self, i: u64) -> ChunkIter<'_> {
+        ChunkIter {
+            chunks: self,
+            worker: i,
+            pos: 0,
+            dynamic: self.schedule == Schedule::Dynamic,
+        }
+    }
+
+    /// Chunks statically owned by worker `i`, used to place buffers before the
+    /// run; nothing is owned with a dynamic schedule.
+    pub fn owned(&self, i: u64) -> ChunkIter<'_> {
+        ChunkIter {
+            chunks: self,
+            worker: if self.schedule == Schedule::Dynamic {
+                self.num_threads
+            } else {
+                i
+            },
+            pos: 0,
+            dynamic: false,
+        }
+    }
+
+    /// Index of the `pos`-th chunk of worker `i` in the static assignment.
+    fn assigned(&self, i: u64, pos: u64) -> Option<u64> {
+        if i >= self.num_threads {
+            return None;
+        }
+        if self.partition == Partition::Strided {
+            let c = i + pos * self.num_threads;
+            return if c < self.num_chunks { Some(c) } else { None };
+        }
+        let per_thread = self.per_thread();
+        if pos >= per_thread {
+            return None;
+        }
+        let k = i * per_thread + pos;
+        if k < self.num_chunks {
+            Some(self.nth(k))
+        } else {
+            None
+        }
+    }
+
+    /// Chunks in each block of the contiguous assignment.
+    fn per_thread(&self) -> u64 {
+        self.num_chunks.div_ceil(self.num_threads)
+    }
+
+    /// Chunk at position `k` of the partition order.
+    fn nth(&self, k: u64) -> u64 {
+        if self.order.is_empty() {
+            k
+        } else {
+            self.order[k as usize]
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+/// Free list of the ring of one worker: a slot is taken when a request is
+/// issued and released once it completes, so that no two requests in flight
+/// share a buffer.
+pub struct Slots {
+    base: usize,
+    chunk_size: usize,
+    /// Indices of the free slots, `None` when not streaming.
+    free: Option<Mutex<Vec<usize>>>,
+}
+
+impl Slots {
+    /// Range of the i/o buffer for the chunk at `r`: `r` itself or, when
+    /// streaming, a free slot.
+    pub fn take(&self, r: Range<usize>) -> Range<usize> {
+        match &self.free {
+            None => r,
+            Some(f) => {
+                let k = f.lock().unwrap().pop().expect("More requests than slots");
+                let b = self.base + k * self.chunk_size;
+                b..b + r.len()
+            }
+        }
+    }
+
+    /// Release the slot starting at `b` in the i/o buffer.
+    pub fn release(&self, b: usize) {
+        if let Some(f) = &self.free {
+            f.lock().unwrap().push((b - self.base) / self.chunk_size);
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+pub struct ChunkIter<'a> {
+    chunks: &'a Chunks,
+    worker: u64,
+    pos: u64,
+    dynamic: bool,
+}
+
+impl<'a> ChunkIter<'a> {
+    /// Share `s` of `n` of the chunks, for workers keeping `n` requests in
+    /// flight: statically assigned chunks are dealt round-robin, dynamically
+    /// scheduled chunks are claimed by whichever share asks first.
+    pub fn share(self, s: usize, n: usize) -> Share<'a> {
+        Share {
+            iter: self,
+            skip: s,
+            n,
+        }
+    }
+
+    /// Merge consecutive chunks into a single range, to be used by the vectored
+    /// engines and to place buffers. Dynamically scheduled chunks are not merged,
+    /// a worker would otherwise claim all the chunks following its first one,
+    /// nor are streamed chunks, each has its own slot.
+    pub fn runs(self) -> Runs<'a> {
+        Runs {
+            iter: self,
+            pending: None,
+        }
+    }
+}
+
+impl<'a> Iterator for ChunkIter<'a> {
+    type Item = Range<usize>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let c = if self.dynamic {
+            let c = self.chunks.next.fetch_add(1, Ordering::Relaxed);
+            if c >= self.chunks.num_chunks {
+                return None;
+            }
+            self.chunks.nth(c)
+        } else {
+            self.chunks.assigned(self.worker, self.pos)?
+        };
+        self.pos += 1;
+        Some(self.chunks.chunk(c))
+    }
+}
+
+pub struct Share<'a> {
+    iter: ChunkIter<'a>,
+    skip: usize,
+    n: usize,
+}
+
+impl<'a> Iterator for Share<'a> {
+    type Item = Range<usize>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.iter.dynamic {
+            return self.iter.next();
+        }
+        for _ in 0..self.skip {
+            self.iter.next()?;
+        }
+        self.skip = self.n - 1;
+        self.iter.next()
+    }
+}
+
+pub struct Runs<'a> {
+    iter: ChunkIter<'a>,
+    pending: Option<Range<usize>>,
+}
+
+impl<'a> Iterator for Runs<'a> {
+    type Item = Range<usize>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut run = self.pending.take().or_else(|| self.iter.next())?;
+        if self.iter.dynamic || self.iter.chunks.slots > 0 {
+            return Some(run);
+        }
+        for r in self.iter.by_ref() {
+            if r.start == run.end {
+                run.end = r.end;
+            } else {
+                self.pending = Some(r);
+                break;
+            }
+        }
+        Some(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(schedule: Schedule, partition: Partition, files: Files) -> Variant {
+        Variant {
+            schedule,
+            partition,
+            files,
+            ..Variant::default()
+        }
+    }
+
+    // every chunk of a 42 byte file of 4 byte chunks, in file order
+    fn expected() -> Vec<Range<usize>> {
+        (0..11).map(|c| c * 4..(c * 4 + 4).min(42)).collect()
+    }
+
+    fn shared(partition: Partition) -> Variant {
+        variant(Schedule::Static, partition, Files::Shared)
+    }
+
+    // chunks of all the workers, in file order
+    fn all_chunks(chunks: &Chunks, threads: u64) -> Vec<Range<usize>> {
+        let mut v: Vec<_> = (0..threads).flat_map(|i| chunks.iter(i)).collect();
+        v.sort_by_key(|r| r.start);
+        v
+    }
+
+    #[test]
+    fn dynamic() {
+        let v = variant(Schedule::Dynamic, Partition::Contiguous, Files::Shared);
+        let chunks = Chunks::new(42, 4, 3, v, 42).unwrap();
+        // the first worker to ask claims the next chunk
+        let mut first = chunks.iter(0);
+        assert_eq!(first.next(), Some(0..4));
+        assert_eq!(chunks.iter(2).next(), Some(4..8));
+        let mut v: Vec<_> = vec![0..4, 4..8];
+        v.extend(first);
+        assert_eq!(v, expected());
+        assert_eq!(chunks.iter(1).next(), None);
+        assert_eq!(chunks.owned(0).count(), 0);
+        assert_eq!(chunks.placed(1), vec![4..8, 16..20, 28..32, 40..42]);
+    }
+
+    #[test]
+    fn contiguous() {
+        let chunks = Chunks::new(42, 4, 3, shared(Partition::Contiguous), 42).unwrap();
+        assert_eq!(
+            chunks.iter(0).collect::<Vec<_>>(),
+            vec![0..4, 4..8, 8..12, 12..16]
+        );
+        assert_eq!(
+            chunks.iter(2).collect::<Vec<_>>(),
+            vec![32..36, 36..40, 40..42]
+        );
+        assert_eq!(chunks.iter(1).runs().collect::<Vec<_>>(), vec![16..32]);
+        assert_eq!(all_chunks(&chunks, 3), expected());
+    }
+
+    #[test]
+    fn strided() {
+        let chunks = Chunks::new(42, 4, 3, shared(Partition::Strided), 42).unwrap();
+        assert_eq!(
+            chunks.iter(1).collect::<Vec<_>>(),
+            vec![4..8, 16..20, 28..32, 40..42]
+        );
+        assert_eq!(chunks.iter(1).runs().count(), 4);
+        assert_eq!(all_chunks(&chunks, 3), expected());
+    }
+
+    #[test]
+    fn random_permutation() {
+        let chunks = Chunks::new(42, 4, 3, shared(Partition::RandomPermutation), 42).unwrap();
+        assert_eq!(all_chunks(&chunks, 3), expected());
+        let again = Chunks::new(42, 4, 3, shared(Partition::RandomPermutation), 42).unwrap();
+        assert!((0..3).all(|i| chunks.iter(i).eq(again.iter(i))));
+    }
+
+    #[test]
+    fn per_thread() {
+        let v = variant(Schedule::Static, Partition::Contiguous, Files::PerThread);
+        let chunks = Chunks::new(42, 4, 3, v, 42).unwrap();
+        assert_eq!(chunks.num_files(), 3);
+        assert_eq!(chunks.file_name("f", 1), "f.1");
+        assert_eq!(
+            (0..3).map(|i| chunks.file_size(i)).collect::<Vec<_>>(),
+            vec![16, 16, 10]
+        );
+        assert_eq!(chunks.offset(1, &(16..20)), 0);
+        assert_eq!(chunks.offset(2, &(40..42)), 8);
+        assert_eq!(chunks.data_offset(2, 8), 40);
+        assert_eq!(chunks.data_offset(0, 12), 12);
+        let shared = Chunks::new(42, 4, 3, shared(Partition::Contiguous), 42).unwrap();
+        assert_eq!(shared.offset(2, &(40..42)), 40);
+        assert_eq!(shared.file_size(2), 42);
+    }
+
+    #[test]
+    fn per_thread_static_contiguous_only() {
+        for v in [
+            variant(Schedule::Dynamic, Partition::Contiguous, Files::PerThread),
+            variant(Schedule::Static, Partition::Strided, Files::PerThread),
+            variant(
+                Schedule::Static,
+                Partition::RandomPermutation,
+                Files::PerThread,
+            ),
+        ] {
+            let err = Chunks::new(42, 4, 3, v, 42).err().unwrap();
+            assert_eq!(err.kind(), IOErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn per_thread_data_size() {
+        let dir = std::env::temp_dir();
+        let fname = dir.join(format!("iobench-data-size-{}", std::process::id()));
+        let fname = fname.to_str().unwrap();
+        let create = |sizes: &[u64]| {
+            for (i, s) in sizes.iter().enumerate() {
+                let f = std::fs::File::create(format!("{}.{}", fname, i)).unwrap();
+                f.set_len(*s).unwrap();
+            }
+        };
+        // the files of 4 threads, fewer threads ignore the last ones
+        create(&[12, 12, 12, 6]);
+        assert_eq!(data_size(fname, Files::PerThread, 4, 4).unwrap(), 42);
+        assert_eq!(data_size(fname, Files::PerThread, 3, 4).unwrap(), 36);
+        create(&[16, 16, 10]);
+        assert_eq!(data_size(fname, Files::PerThread, 3, 4).unwrap(), 42);
+        assert_eq!(data_size(fname, Files::PerThread, 1, 4).unwrap(), 16);
+        // a block of 16 bytes then the last 10
+        create(&[10, 16]);
+        let err = data_size(fname, Files::PerThread, 2, 4).err().unwrap();
+        assert_eq!(err.kind(), IOErrorKind::InvalidInput);
+        let err = data_size(fname, Files::PerThread, 5, 4).err().unwrap();
+        assert_eq!(err.kind(), IOErrorKind::NotFound);
+        for i in 0..4 {
+            std::fs::remove_file(format!("{}.{}", fname, i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn buffer() {
+        let chunks = Chunks::new(42, 4, 3, shared(Partition::Contiguous), 42).unwrap();
+        assert_eq!(chunks.buf(2, 40..42), 40..42);
+        assert_eq!(chunks.placed(1), vec![16..32]);
+    }
+
+    #[test]
+    fn streaming_slots() {
+        // 2 slots of 4 bytes per worker
+        let chunks = Chunks::new(64, 4, 2, shared(Partition::Contiguous), 16).unwrap();
+        assert_eq!(chunks.placed(1), vec![8..16]);
+        let slots: Vec<_> = chunks.iter(1).map(|r| chunks.buf(1, r)).collect();
+        assert_eq!(slots[..3], [8..12, 12..16, 8..12]);
+        assert!(slots.iter().all(|s| s.start >= 8 && s.end <= 16));
+        // runs are not merged when streaming
+        assert_eq!(chunks.iter(0).runs().count(), 8);
+        let strided = Chunks::new(64, 4, 2, shared(Partition::Strided), 16).unwrap();
+        let slots: Vec<_> = strided.iter(0).map(|r| strided.buf(0, r)).collect();
+        assert_eq!(slots[..3], [0..4, 4..8, 0..4]);
+    }
+
+    #[test]
+    fn streaming_buffer_too_small() {
+        let err = Chunks::new(64, 4, 2, shared(Partition::Contiguous), 7).err();
+        assert_eq!(err.unwrap().kind(), IOErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn free_slots() {
+        let chunks = Chunks::new(64, 4, 2, shared(Partition::Contiguous), 16).unwrap();
+        assert!(chunks.free_slots(1, 3).is_err());
+        let slots = chunks.free_slots(1, 2).unwrap();
+        let a = slots.take(32..36);
+        let b = slots.take(36..38);
+        assert_eq!((a.clone(), b.clone()), (8..12, 12..14));
+        slots.release(a.start);
+        assert_eq!(slots.take(40..44), 8..12);
+        // the whole data fits: chunks stay in place
+        let chunks = Chunks::new(64, 4, 2, shared(Partition::Contiguous), 64).unwrap();
+        let slots = chunks.free_slots(0, 8).unwrap();
+        assert_eq!(slots.take(4..8), 4..8);
+    }
+}
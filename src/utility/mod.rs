@@ -1,6 +1,39 @@
 #![allow(non_camel_case_types)]
+use crate::config::MmapConfig;
+use std::ops::Range;
 use std::os::raw::{c_int, c_void};
 use std::os::unix::io::RawFd;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+/// `FromStr` and `Display` of an enum set from the command line, from the
+/// option value of each variant; `what` names the option in the error of an
+/// unknown value.
+macro_rules! option_enum {
+    ($t:ident, $what:expr, { $($s:expr => $v:ident),+ $(,)? }) => {
+        impl std::str::FromStr for $t {
+            type Err = std::io::Error;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($s => Ok($t::$v),)+
+                    _ => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Unknown {} '{}', use {}", $what, s, [$($s),+].join("|")).as_str(),
+                    )),
+                }
+            }
+        }
+
+        impl std::fmt::Display for $t {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    $($t::$v => write!(f, $s),)+
+                }
+            }
+        }
+    };
+}
+pub(crate) use option_enum;
+
 pub struct Movable<T>(pub *const T);
 impl<T> Movable<T> {
     pub fn get(&self) -> Option<*const T> {
@@ -23,14 +56,233 @@ impl<T> MovableMut<T> {
 
 unsafe impl<T> Send for Movable<T> {}
 unsafe impl<T> Send for MovableMut<T> {}
+
+impl Movable<u8> {
+    /// Range `r` of the pointed-to buffer.
+    ///
+    /// # Safety
+    /// The range must be inside the buffer, which must outlive the returned slice.
+    pub unsafe fn slice<'a>(&self, r: Range<usize>) -> &'a [u8] {
+        std::slice::from_raw_parts(self.0.add(r.start), r.len())
+    }
+}
+
+impl MovableMut<u8> {
+    /// Range `r` of the pointed-to buffer.
+    ///
+    /// # Safety
+    /// As `Movable::slice`; in addition ranges accessed from different threads must
+    /// not overlap.
+    pub unsafe fn slice_mut<'a>(&self, r: Range<usize>) -> &'a mut [u8] {
+        std::slice::from_raw_parts_mut(self.0.add(r.start), r.len())
+    }
+}
+//----------------------------------------------------------------------------
+/// Atomic counter in a shared anonymous mapping, so that it is also shared by
+/// forked worker processes.
+pub struct SharedCounter(*mut AtomicU64);
+
+unsafe impl Send for SharedCounter {}
+unsafe impl Sync for SharedCounter {}
+
+impl SharedCounter {
+    pub fn new() -> Self {
+        let p = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                std::mem::size_of::<AtomicU64>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if p == libc::MAP_FAILED {
+            panic!(
+                "Failed to map shared counter: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        // the mapping is zero filled
+        SharedCounter(p as *mut AtomicU64)
+    }
+}
+
+impl Default for SharedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::ops::Deref for SharedCounter {
+    type Target = AtomicU64;
+    fn deref(&self) -> &AtomicU64 {
+        unsafe { &*self.0 }
+    }
+}
+
+impl Drop for SharedCounter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(
+                self.0 as *mut libc::c_void,
+                std::mem::size_of::<AtomicU64>(),
+            );
+        }
+    }
+}
+
 //----------j------------------------------------------------------------------
 pub fn dump(v: &[u8]) -> std::io::Result<()> {
     use std::io::Write;
     let mut f = std::fs::File::open("/dev/null")?;
-    let _ = f.write(&v[..1]);
+    let _ = f.write(&v[..v.len().min(1)]);
     Ok(())
 }
 
+//----------------------------------------------------------------------------
+/// Size of `fname`, see `schedule::data_size` for the per-thread files.
+pub fn file_size(fname: &str) -> std::io::Result<u64> {
+    Ok(std::fs::metadata(fname)?.len())
+}
+
+//----------------------------------------------------------------------------
+/// Part of a buffer holding the data of the sequential engines: all of it
+/// when it fits, otherwise a ring of as many chunks as the buffer can hold.
+pub struct Ring(usize);
+
+impl Ring {
+    pub fn new(buf_len: usize, size: u64, chunk_size: u64) -> std::io::Result<Ring> {
+        if buf_len as u64 >= size {
+            return Ok(Ring(size as usize));
+        }
+        let len = buf_len / chunk_size as usize * chunk_size as usize;
+        if len == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Buffer of {} bytes too small for a chunk of {} bytes",
+                    buf_len, chunk_size
+                )
+                .as_str(),
+            ));
+        }
+        Ok(Ring(len))
+    }
+    pub fn capacity(&self) -> usize {
+        self.0
+    }
+    /// Buffer range of the data range `r`, which must not span two chunks.
+    pub fn slot(&self, r: std::ops::Range<usize>) -> std::ops::Range<usize> {
+        let b = r.start % self.0.max(1);
+        b..b + r.len()
+    }
+}
+
+//----------------------------------------------------------------------------
+/// Positional command line argument `n`, `--name[=value]` options are skipped.
+pub fn arg(n: usize) -> Option<String> {
+    std::env::args().filter(|a| !a.starts_with("--")).nth(n)
+}
+
+/// Value of command line option `--name=value`, empty string for a bare `--name`.
+pub fn opt_arg(name: &str) -> Option<String> {
+    let prefix = format!("--{}", name);
+    std::env::args().find_map(|a| match a.strip_prefix(&prefix) {
+        Some("") => Some(String::new()),
+        Some(v) => v.strip_prefix('=').map(|v| v.to_owned()),
+        None => None,
+    })
+}
+
+//----------------------------------------------------------------------------
+/// Parse a size with an optional binary suffix: `4096`, `4K`, `64M`, `1G`.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, mul) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 0x400),
+        'm' | 'M' => (&s[..s.len() - 1], 0x100000),
+        'g' | 'G' => (&s[..s.len() - 1], 0x40000000),
+        _ => (s, 1),
+    };
+    num.parse::<u64>().ok()?.checked_mul(mul)
+}
+
+/// Parse a geometric sweep `first..last xfactor`, e.g. `4K..64M x2`.
+/// A plain size is a sweep with a single element, a missing factor means `x2`.
+/// Only a trailing `x<n>` is a factor; the sweep stops before overflowing and
+/// always ends with `last`.
+pub fn parse_sweep(s: &str) -> Option<Vec<u64>> {
+    let (range, factor) = match s.rsplit_once('x') {
+        Some((r, f)) if !f.trim().is_empty() && f.trim().bytes().all(|b| b.is_ascii_digit()) => {
+            (r, f.trim().parse::<u64>().ok()?)
+        }
+        _ => (s, 2),
+    };
+    let (first, last) = match range.split_once("..") {
+        Some((f, l)) => (parse_size(f)?, parse_size(l)?),
+        None => {
+            let v = parse_size(range)?;
+            (v, v)
+        }
+    };
+    if first == 0 || factor < 2 || last < first {
+        return None;
+    }
+    let mut v = Vec::new();
+    let mut s = first;
+    while s <= last {
+        v.push(s);
+        match s.checked_mul(factor) {
+            Some(n) => s = n,
+            None => break,
+        }
+    }
+    // `1..6` is 1, 2, 4, 6: the last value is always run
+    if v.last() != Some(&last) {
+        v.push(last);
+    }
+    Some(v)
+}
+
+/// Format a size using the largest binary suffix that divides it exactly.
+pub fn fmt_size(s: u64) -> String {
+    if s >= 0x40000000 && s.is_multiple_of(0x40000000) {
+        format!("{}G", s / 0x40000000)
+    } else if s >= 0x100000 && s.is_multiple_of(0x100000) {
+        format!("{}M", s / 0x100000)
+    } else if s >= 0x400 && s.is_multiple_of(0x400) {
+        format!("{}K", s / 0x400)
+    } else {
+        format!("{}", s)
+    }
+}
+
+/// Print throughput, speedup and parallel efficiency of a thread scaling run.
+/// `results` holds `(num_threads, GiB/s)` pairs, the first one is the baseline.
+pub fn print_scaling(name: &str, results: &[(u64, f64)]) {
+    let (t0, r0) = match results.first() {
+        Some(r) => *r,
+        None => return,
+    };
+    println!("{}:", name);
+    println!(
+        "{:>10}{:>12}{:>10}{:>12}",
+        "threads", "GiB/s", "speedup", "efficiency"
+    );
+    for (t, r) in results {
+        let speedup = r / r0;
+        let efficiency = speedup * t0 as f64 / *t as f64;
+        println!(
+            "{:>10}{:>12.2}{:>10.2}{:>11.0}%",
+            t,
+            r,
+            speedup,
+            efficiency * 100.
+        );
+    }
+}
+
 //----------------------------------------------------------------------------
 pub type ssize_t = isize;
 pub type size_t = usize;
@@ -40,6 +292,290 @@ extern "C" {
     pub fn pwrite(fd: RawFd, buf: *mut c_void, count: size_t, offset: off_t) -> ssize_t;
 }
 
+/// `pread` until `buf` is full, fails on end of file.
+pub fn pread_exact(fd: RawFd, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    let mut r = 0;
+    while r < buf.len() {
+        let ret = unsafe {
+            pread(
+                fd,
+                buf[r..].as_mut_ptr() as *mut c_void,
+                (buf.len() - r) as size_t,
+                (offset as usize + r) as off_t,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if ret == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        r += ret as usize;
+    }
+    Ok(())
+}
+
+/// `pwrite` until all of `buf` is written.
+pub fn pwrite_all(fd: RawFd, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    let mut w = 0;
+    while w < buf.len() {
+        let ret = unsafe {
+            pwrite(
+                fd,
+                buf[w..].as_ptr() as *mut c_void,
+                (buf.len() - w) as size_t,
+                (offset as usize + w) as off_t,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        w += ret as usize;
+    }
+    Ok(())
+}
+
+/// `pread_exact` the part of `buf` before `direct_end` through the direct
+/// descriptor `fd`, the unaligned rest through the buffered `tail_fd`.
+pub fn pread_split(
+    fd: RawFd,
+    tail_fd: RawFd,
+    buf: &mut [u8],
+    offset: u64,
+    direct_end: u64,
+) -> std::io::Result<()> {
+    let d = (direct_end.saturating_sub(offset) as usize).min(buf.len());
+    let (head, tail) = buf.split_at_mut(d);
+    pread_exact(fd, head, offset)?;
+    if !tail.is_empty() {
+        pread_exact(tail_fd, tail, offset + d as u64)?;
+    }
+    Ok(())
+}
+
+/// `pwrite_all` counterpart of `pread_split`.
+pub fn pwrite_split(
+    fd: RawFd,
+    tail_fd: RawFd,
+    buf: &[u8],
+    offset: u64,
+    direct_end: u64,
+) -> std::io::Result<()> {
+    let d = (direct_end.saturating_sub(offset) as usize).min(buf.len());
+    let (head, tail) = buf.split_at(d);
+    pwrite_all(fd, head, offset)?;
+    if !tail.is_empty() {
+        pwrite_all(tail_fd, tail, offset + d as u64)?;
+    }
+    Ok(())
+}
+
+/// `copy_file_range` until `len` bytes are copied from `offset` of `fd_in` to
+/// the same offset of `fd_out`, fails on end of file.
+#[cfg(target_os = "linux")]
+pub fn copy_file_range_all(
+    fd_in: RawFd,
+    fd_out: RawFd,
+    offset: u64,
+    len: usize,
+) -> std::io::Result<()> {
+    let mut off_in = offset as libc::loff_t;
+    let mut off_out = offset as libc::loff_t;
+    let mut left = len;
+    while left > 0 {
+        let ret =
+            unsafe { libc::copy_file_range(fd_in, &mut off_in, fd_out, &mut off_out, left, 0) };
+        if ret < 0 {
+            return Err(unsupported(
+                "copy_file_range",
+                std::io::Error::last_os_error(),
+            ));
+        }
+        if ret == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        left -= ret as usize;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy_file_range_all(
+    _fd_in: RawFd,
+    _fd_out: RawFd,
+    _offset: u64,
+    _len: usize,
+) -> std::io::Result<()> {
+    Err(linux_only("copy_file_range"))
+}
+
+/// `sendfile` until `len` bytes from `offset` of `fd_in` are written to
+/// `fd_out` at its current position, fails on end of file.
+#[cfg(target_os = "linux")]
+pub fn sendfile_all(fd_out: RawFd, fd_in: RawFd, offset: u64, len: usize) -> std::io::Result<()> {
+    let mut off = offset as libc::off_t;
+    let mut left = len;
+    while left > 0 {
+        let ret = unsafe { libc::sendfile(fd_out, fd_in, &mut off, left) };
+        if ret < 0 {
+            return Err(unsupported("sendfile", std::io::Error::last_os_error()));
+        }
+        if ret == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        left -= ret as usize;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sendfile_all(
+    _fd_out: RawFd,
+    _fd_in: RawFd,
+    _offset: u64,
+    _len: usize,
+) -> std::io::Result<()> {
+    Err(linux_only("sendfile"))
+}
+
+/// Pipe buffering the data moved by `splice_all`.
+pub struct Pipe {
+    read: RawFd,
+    write: RawFd,
+    /// Capacity in bytes.
+    pub size: usize,
+}
+
+impl Pipe {
+    /// Pipe of `size` bytes if allowed, `/proc/sys/fs/pipe-max-size` caps the
+    /// size for unprivileged users.
+    #[cfg(target_os = "linux")]
+    pub fn new(size: usize) -> std::io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        unsafe { libc::fcntl(fds[1], libc::F_SETPIPE_SZ, size as libc::c_int) };
+        let size = unsafe { libc::fcntl(fds[1], libc::F_GETPIPE_SZ) };
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Pipe {
+            read: fds[0],
+            write: fds[1],
+            size: size as usize,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_size: usize) -> std::io::Result<Self> {
+        Err(linux_only("splice"))
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+// `splice` of up to `len` bytes, from the current position of a descriptor
+// without offset
+#[cfg(target_os = "linux")]
+fn splice(
+    fd_in: RawFd,
+    off_in: Option<&mut libc::loff_t>,
+    fd_out: RawFd,
+    off_out: Option<&mut libc::loff_t>,
+    len: usize,
+) -> std::io::Result<usize> {
+    let p = |o: Option<&mut libc::loff_t>| o.map_or(std::ptr::null_mut(), |o| o as *mut _);
+    let ret = unsafe {
+        libc::splice(
+            fd_in,
+            p(off_in),
+            fd_out,
+            p(off_out),
+            len,
+            libc::SPLICE_F_MOVE,
+        )
+    };
+    if ret < 0 {
+        return Err(unsupported("splice", std::io::Error::last_os_error()));
+    }
+    Ok(ret as usize)
+}
+
+/// `splice` `len` bytes from `fd_in` to `fd_out` through `pipe`, a `None`
+/// offset reads or writes at the current position of a socket or file.
+#[cfg(target_os = "linux")]
+pub fn splice_all(
+    fd_in: RawFd,
+    off_in: Option<u64>,
+    fd_out: RawFd,
+    off_out: Option<u64>,
+    len: usize,
+    pipe: &Pipe,
+) -> std::io::Result<()> {
+    let mut off_in = off_in.map(|o| o as libc::loff_t);
+    let mut off_out = off_out.map(|o| o as libc::loff_t);
+    let mut left = len;
+    while left > 0 {
+        let n = splice(
+            fd_in,
+            off_in.as_mut(),
+            pipe.write,
+            None,
+            left.min(pipe.size),
+        )?;
+        if n == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        let mut m = 0;
+        while m < n {
+            m += splice(pipe.read, None, fd_out, off_out.as_mut(), n - m)?;
+        }
+        left -= n;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn splice_all(
+    _fd_in: RawFd,
+    _off_in: Option<u64>,
+    _fd_out: RawFd,
+    _off_out: Option<u64>,
+    _len: usize,
+    _pipe: &Pipe,
+) -> std::io::Result<()> {
+    Err(linux_only("splice"))
+}
+
+/// Errors of file systems, or kernels, not supporting a call become
+/// `Unsupported`, other errors are returned as they are.
+pub fn unsupported(call: &str, e: std::io::Error) -> std::io::Error {
+    match e.raw_os_error() {
+        Some(libc::EOPNOTSUPP | libc::ENOSYS | libc::EXDEV | libc::ENOTTY) => std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} not supported: {}", call, e).as_str(),
+        ),
+        _ => e,
+    }
+}
+
+/// `Unsupported` error of the Linux-only calls on other systems.
+#[cfg(not(target_os = "linux"))]
+fn linux_only(call: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{} not supported: Linux only", call).as_str(),
+    )
+}
+
 #[repr(C)]
 pub struct IoVec {
     pub iov_base: *mut c_void,
@@ -51,23 +587,399 @@ extern "C" {
     pub fn writev(fd: RawFd, bufs: *const IoVec, count: c_int) -> ssize_t;
     pub fn preadv(fd: RawFd, bufs: *const IoVec, count: c_int, offset: off_t) -> ssize_t;
     pub fn pwritev(fd: RawFd, bufs: *const IoVec, count: c_int, offset: off_t) -> ssize_t;
+    pub fn preadv2(
+        fd: RawFd,
+        bufs: *const IoVec,
+        count: c_int,
+        offset: off_t,
+        flags: c_int,
+    ) -> ssize_t;
+    pub fn pwritev2(
+        fd: RawFd,
+        bufs: *const IoVec,
+        count: c_int,
+        offset: off_t,
+        flags: c_int,
+    ) -> ssize_t;
 }
 
-macro_rules! join_and_check {
-    ($threads:expr) => {
-        for t in $threads {
-            match t.join() {
-                Err(e) => 
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!("Error joining thread: {:?}", e).as_str()
-                    )),
-                Ok(ret) => if let Err(e) = ret {
-                    return Err(e)
-                } 
-            }
+// per-call flags of preadv2/pwritev2, linux/fs.h
+pub const RWF_HIPRI: c_int = 0x01;
+pub const RWF_DSYNC: c_int = 0x02;
+pub const RWF_SYNC: c_int = 0x04;
+pub const RWF_NOWAIT: c_int = 0x08;
+pub const RWF_APPEND: c_int = 0x10;
+
+/// Parse a list of RWF flags: `hipri,nowait,dsync,sync,append`. Polling only
+/// applies to direct i/o: with `hipri` the preadv2/pwritev2 engines open the
+/// file with `O_DIRECT`, which requires aligned buffers and chunks.
+pub fn parse_rwf(s: &str) -> Option<c_int> {
+    let mut flags = 0;
+    for f in s.split(',').filter(|f| !f.is_empty()) {
+        flags |= match f {
+            "hipri" => RWF_HIPRI,
+            "nowait" => RWF_NOWAIT,
+            "dsync" => RWF_DSYNC,
+            "sync" => RWF_SYNC,
+            "append" => RWF_APPEND,
+            _ => return None,
+        };
+    }
+    Some(flags)
+}
+
+//----------------------------------------------------------------------------
+// direct i/o alignment, `struct statx` from linux/stat.h
+#[cfg(target_os = "linux")]
+const STATX_DIOALIGN: u32 = 0x2000;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct StatxTimestamp {
+    tv_sec: i64,
+    tv_nsec: u32,
+    reserved: i32,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct Statx {
+    stx_mask: u32,
+    stx_blksize: u32,
+    stx_attributes: u64,
+    stx_nlink: u32,
+    stx_uid: u32,
+    stx_gid: u32,
+    stx_mode: u16,
+    spare0: u16,
+    stx_ino: u64,
+    stx_size: u64,
+    stx_blocks: u64,
+    stx_attributes_mask: u64,
+    stx_atime: StatxTimestamp,
+    stx_btime: StatxTimestamp,
+    stx_ctime: StatxTimestamp,
+    stx_mtime: StatxTimestamp,
+    stx_rdev_major: u32,
+    stx_rdev_minor: u32,
+    stx_dev_major: u32,
+    stx_dev_minor: u32,
+    stx_mnt_id: u64,
+    stx_dio_mem_align: u32,
+    stx_dio_offset_align: u32,
+    spare3: [u64; 12],
+}
+
+/// Memory and file offset/length alignment required by `O_DIRECT` transfers.
+#[derive(Clone, Copy, Debug)]
+pub struct DioAlign {
+    pub mem: u64,
+    pub offset: u64,
+}
+
+/// Direct i/o alignment of `path`, or of its directory when it does not exist
+/// yet: `statx(STATX_DIOALIGN)` where supported, otherwise the logical block
+/// size of the underlying device, otherwise 512.
+#[cfg(target_os = "linux")]
+pub fn dio_align(path: &str) -> std::io::Result<DioAlign> {
+    use std::os::unix::ffi::OsStrExt;
+    let p = std::path::Path::new(path);
+    let p = if p.exists() {
+        p
+    } else {
+        match p.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d,
+            _ => std::path::Path::new("."),
+        }
+    };
+    let cpath = std::ffi::CString::new(p.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stx = Statx::default();
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            libc::AT_FDCWD,
+            cpath.as_ptr(),
+            0,
+            STATX_DIOALIGN,
+            &mut stx as *mut Statx,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if stx.stx_mask & STATX_DIOALIGN != 0 {
+        if stx.stx_dio_offset_align == 0 {
+            return Err(std::io::Error::other(
+                format!("'{}' does not support O_DIRECT", path).as_str(),
+            ));
+        }
+        return Ok(DioAlign {
+            mem: stx.stx_dio_mem_align as u64,
+            offset: stx.stx_dio_offset_align as u64,
+        });
+    }
+    // partitions have no queue directory, use the parent device's
+    let dev = format!("/sys/dev/block/{}:{}", stx.stx_dev_major, stx.stx_dev_minor);
+    let lbs = ["queue/logical_block_size", "../queue/logical_block_size"]
+        .iter()
+        .find_map(|f| std::fs::read_to_string(format!("{}/{}", dev, f)).ok())
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(512);
+    Ok(DioAlign {
+        mem: lbs,
+        offset: lbs,
+    })
+}
+
+/// Without `statx` the alignment is unknown, assume 512.
+#[cfg(not(target_os = "linux"))]
+pub fn dio_align(_path: &str) -> std::io::Result<DioAlign> {
+    Ok(DioAlign {
+        mem: 512,
+        offset: 512,
+    })
+}
+
+/// Check the address of `buf`, unless allocated by the i/o library, `chunk_size`
+/// and the total `size`, unless the unaligned tail is transferred separately,
+/// against the direct i/o alignment of `path`.
+pub fn check_dio_align(
+    path: &str,
+    buf: Option<*const u8>,
+    chunk_size: u64,
+    size: Option<u64>,
+) -> std::io::Result<DioAlign> {
+    let a = dio_align(path)?;
+    let err = |what: &str, align: u64| {
+        Err(std::io::Error::other(
+            format!(
+                "O_DIRECT on '{}' requires {} {}, memory alignment: {}, offset alignment: {}",
+                path, what, align, a.mem, a.offset
+            )
+            .as_str(),
+        ))
+    };
+    if let Some(b) = buf {
+        if !(b as u64).is_multiple_of(a.mem) {
+            return err("a buffer address aligned to", a.mem);
         }
     }
+    let chunk_align = if buf.is_some() {
+        a.offset.max(a.mem)
+    } else {
+        a.offset
+    };
+    if !chunk_size.is_multiple_of(chunk_align) {
+        return err("a chunk size multiple of", chunk_align);
+    }
+    if size.is_some_and(|s| !s.is_multiple_of(a.offset)) {
+        return err("a file size multiple of", a.offset);
+    }
+    Ok(a)
+}
+
+//----------------------------------------------------------------------------
+// mmap
+#[cfg(target_os = "linux")]
+const MADV_POPULATE_READ: c_int = 22;
+
+/// Parse a list of madvise advice: `sequential,willneed` and, on Linux,
+/// `hugepage,populate-read`.
+pub fn parse_madvise(s: &str) -> Option<Vec<c_int>> {
+    s.split(',')
+        .filter(|a| !a.is_empty())
+        .map(|a| match a {
+            "sequential" => Some(libc::MADV_SEQUENTIAL),
+            "willneed" => Some(libc::MADV_WILLNEED),
+            #[cfg(target_os = "linux")]
+            "hugepage" => Some(libc::MADV_HUGEPAGE),
+            #[cfg(target_os = "linux")]
+            "populate-read" => Some(MADV_POPULATE_READ),
+            _ => None,
+        })
+        .collect()
+}
+
+// apply `advice` to the whole mapping
+fn madvise(mmap: &[u8], advice: &[c_int]) -> std::io::Result<()> {
+    if mmap.is_empty() {
+        return Ok(());
+    }
+    for &a in advice {
+        let ret = unsafe { libc::madvise(mmap.as_ptr() as *mut c_void, mmap.len(), a) };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(std::io::Error::new(
+                err.kind(),
+                format!("madvise({}) failed: {}", a, err).as_str(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Time spent mapping and prefaulting a file, copying from or to the mapping
+/// and, for writes, flushing the dirty pages.
+#[derive(Clone, Copy, Debug)]
+pub struct MmapTiming {
+    pub prefault: Duration,
+    pub copy: Duration,
+    pub writeback: Duration,
+}
+
+impl MmapTiming {
+    pub fn elapsed(&self) -> Duration {
+        self.prefault + self.copy + self.writeback
+    }
+}
+
+/// Map `file` read-only with the options in `cfg`, returns the mapping and the
+/// time taken, which includes `MAP_POPULATE` and `madvise`.
+pub fn map_file(
+    file: &std::fs::File,
+    cfg: &MmapConfig,
+) -> std::io::Result<(memmap2::Mmap, Duration)> {
+    let t = Instant::now();
+    let mut opts = memmap2::MmapOptions::new();
+    if cfg.populate {
+        opts.populate();
+    }
+    let mmap = unsafe { opts.map(file)? };
+    madvise(&mmap, &cfg.madvise)?;
+    Ok((mmap, t.elapsed()))
 }
-pub(crate) use join_and_check;
 
+/// Map the first `len` bytes of `file` writable with the options in `cfg`,
+/// returns the mapping and the time taken.
+pub fn map_file_mut(
+    file: &std::fs::File,
+    len: usize,
+    cfg: &MmapConfig,
+) -> std::io::Result<(memmap2::MmapMut, Duration)> {
+    let t = Instant::now();
+    let mut opts = memmap2::MmapOptions::new();
+    opts.len(len);
+    if cfg.populate {
+        opts.populate();
+    }
+    let mmap = unsafe {
+        if cfg.private {
+            opts.map_copy(file)?
+        } else {
+            opts.map_mut(file)?
+        }
+    };
+    madvise(&mmap, &cfg.madvise)?;
+    Ok((mmap, t.elapsed()))
+}
+
+/// Flush `len` bytes at `offset` of the mapping as selected in `cfg`, returns
+/// the time taken.
+pub fn flush_range(
+    mmap: &memmap2::MmapMut,
+    offset: usize,
+    len: usize,
+    cfg: &MmapConfig,
+) -> std::io::Result<Duration> {
+    let t = Instant::now();
+    if cfg.flush_async {
+        mmap.flush_async_range(offset, len)?;
+    } else {
+        mmap.flush_range(offset, len)?;
+    }
+    Ok(t.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("4k"), Some(0x1000));
+        assert_eq!(parse_size(" 64M "), Some(0x4000000));
+        assert_eq!(parse_size("1G"), Some(0x40000000));
+        assert_eq!(parse_size("18446744073709551615"), Some(u64::MAX));
+    }
+
+    #[test]
+    fn size_errors() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("1T"), None);
+        // 2^34 GiB does not fit in 64 bits
+        assert_eq!(parse_size("17179869184G"), None);
+        assert_eq!(parse_size("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn sweep() {
+        assert_eq!(parse_sweep("4K"), Some(vec![0x1000]));
+        assert_eq!(parse_sweep("1..8"), Some(vec![1, 2, 4, 8]));
+        assert_eq!(
+            parse_sweep("4K..64K x4"),
+            Some(vec![0x1000, 0x4000, 0x10000])
+        );
+        assert_eq!(parse_sweep("1..32x8"), Some(vec![1, 8, 32]));
+        assert_eq!(parse_sweep("3..3"), Some(vec![3]));
+    }
+
+    #[test]
+    fn sweep_overflow() {
+        let v = parse_sweep("1..18446744073709551615").unwrap();
+        assert_eq!(v.len(), 65);
+        assert_eq!(v[63], 1 << 63);
+        assert_eq!(v.last(), Some(&u64::MAX));
+        let v = parse_sweep("1G..16G x4294967296").unwrap();
+        assert_eq!(v, vec![0x40000000, 0x400000000]);
+    }
+
+    #[test]
+    fn sweep_errors() {
+        assert_eq!(parse_sweep(""), None);
+        assert_eq!(parse_sweep("0..8"), None);
+        assert_eq!(parse_sweep("8..4"), None);
+        assert_eq!(parse_sweep("1..8x1"), None);
+        assert_eq!(parse_sweep("1..8x0"), None);
+        assert_eq!(parse_sweep("1..8xz"), None);
+        assert_eq!(parse_sweep("1..8G..16G"), None);
+    }
+
+    #[test]
+    fn sweep_ends_with_last() {
+        assert_eq!(parse_sweep("1..6"), Some(vec![1, 2, 4, 6]));
+        assert_eq!(parse_sweep("1..10x3"), Some(vec![1, 3, 9, 10]));
+    }
+
+    #[test]
+    fn rwf() {
+        assert_eq!(parse_rwf(""), Some(0));
+        assert_eq!(parse_rwf("hipri"), Some(RWF_HIPRI));
+        assert_eq!(
+            parse_rwf("nowait,dsync,sync,append"),
+            Some(RWF_NOWAIT | RWF_DSYNC | RWF_SYNC | RWF_APPEND)
+        );
+        assert_eq!(parse_rwf("hipri,bogus"), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn madvise() {
+        assert_eq!(parse_madvise(""), Some(vec![]));
+        assert_eq!(
+            parse_madvise("sequential,willneed,hugepage,populate-read"),
+            Some(vec![
+                libc::MADV_SEQUENTIAL,
+                libc::MADV_WILLNEED,
+                libc::MADV_HUGEPAGE,
+                MADV_POPULATE_READ
+            ])
+        );
+        assert_eq!(parse_madvise("random"), None);
+    }
+}
@@ -59,7 +59,7 @@ pub fn read_vec_slice(
         while r < bytes && iovec_offset < iovecs.len() {
             let b = readv(
                 fd,
-                iovecs[iovec_offset..].as_ptr() as *const IoVec,
+                iovecs[iovec_offset..].as_ptr(),
                 (iovecs.len() - iovec_offset) as c_int,
             );
             if b < 0 {
@@ -119,7 +119,7 @@ pub fn read_vec_slice_offset(
         while r < bytes && iovec_offset < iovecs.len() {
             let b = preadv(
                 fd,
-                iovecs[iovec_offset..].as_ptr() as *const IoVec,
+                iovecs[iovec_offset..].as_ptr(),
                 (iovecs.len() - iovec_offset) as c_int,
                 offset,
             );
@@ -180,7 +180,7 @@ pub fn write_vec_slice(
         while w < bytes && iovec_offset < iovecs.len() {
             let b = writev(
                 fd,
-                iovecs[iovec_offset..].as_ptr() as *const IoVec,
+                iovecs[iovec_offset..].as_ptr(),
                 (iovecs.len() - iovec_offset) as c_int,
             );
             if b < 0 {
@@ -201,7 +201,7 @@ pub fn write_vec_slice(
             let base = ptr_offset(iv.iov_base, (iv.iov_len - d) as isize) as *const u8;
             let len = d;
             let s = std::slice::from_raw_parts(base, len);
-            file.write_all(&s)?;
+            file.write_all(s)?;
             w += b as usize + s.len();
         }
     }
@@ -240,7 +240,7 @@ pub fn write_vec_slice_offset(
         while w < bytes && iovec_offset < iovecs.len() {
             let b = pwritev(
                 fd,
-                iovecs[iovec_offset..].as_ptr() as *const IoVec,
+                iovecs[iovec_offset..].as_ptr(),
                 (iovecs.len() - iovec_offset) as c_int,
                 offset,
             );
@@ -262,10 +262,135 @@ pub fn write_vec_slice_offset(
             let base = ptr_offset(iv.iov_base, (iv.iov_len - d) as isize) as *const u8;
             let len = d;
             let s = std::slice::from_raw_parts(base, len);
-            file.write_all(&s)?;
+            file.write_all(s)?;
             w += b as usize + s.len();
             offset = w as isize;
         }
     }
     Ok(())
 }
+
+// ----------------------------------------------------------------------------
+// preadv2/pwritev2 with per-call RWF flags. Partial transfers are resumed from
+// the first iovec not completely transferred; at most IOV_MAX iovecs are passed
+// to each call.
+const IOV_MAX: usize = 1024;
+
+fn iovecs_of(buf: &[u8], chunk_size: u64) -> Vec<IoVec> {
+    let mut iovecs = Vec::new();
+    let mut bytes = 0_usize;
+    while bytes < buf.len() {
+        let b = bytes;
+        let e = (b + chunk_size as usize).min(buf.len());
+        iovecs.push(IoVec {
+            iov_base: buf[b..e].as_ptr() as *mut c_void,
+            iov_len: (e - b) as size_t,
+        });
+        bytes += e - b;
+    }
+    iovecs
+}
+
+// skip the `n` bytes transferred starting at iovec `first`
+fn advance(iovecs: &mut [IoVec], first: &mut usize, mut n: usize) {
+    while n > 0 {
+        let iv = &mut iovecs[*first];
+        if n >= iv.iov_len {
+            n -= iv.iov_len;
+            *first += 1;
+        } else {
+            iv.iov_base = ptr_offset_mut(iv.iov_base, n as isize);
+            iv.iov_len -= n;
+            n = 0;
+        }
+    }
+}
+
+/// Read `buf` at `offset` with `preadv2`; a call failing with `EAGAIN` because
+/// of `RWF_NOWAIT` is counted and retried without the flag.
+/// Returns the number of `EAGAIN` errors.
+pub fn read_vec_slice_offset_flags(
+    file: &std::fs::File,
+    buf: &mut [u8],
+    chunk_size: u64,
+    mut offset: off_t,
+    flags: c_int,
+) -> std::io::Result<u64> {
+    let fd = file.as_raw_fd();
+    let mut iovecs = iovecs_of(buf, chunk_size);
+    let mut first = 0;
+    let mut eagain = 0;
+    let mut f = flags;
+    while first < iovecs.len() {
+        let b = unsafe {
+            preadv2(
+                fd,
+                iovecs[first..].as_ptr(),
+                (iovecs.len() - first).min(IOV_MAX) as c_int,
+                offset,
+                f,
+            )
+        };
+        if b < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::WouldBlock && f & RWF_NOWAIT != 0 {
+                eagain += 1;
+                f = flags & !RWF_NOWAIT;
+                continue;
+            }
+            return Err(err);
+        }
+        if b == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        f = flags;
+        advance(&mut iovecs, &mut first, b as usize);
+        offset += b;
+    }
+    Ok(eagain)
+}
+
+/// Write `buf` at `offset` with `pwritev2`; a call failing with `EAGAIN`
+/// because of `RWF_NOWAIT` is counted and retried without the flag. With
+/// `RWF_APPEND` the offset is ignored and data is appended to the file.
+/// Returns the number of `EAGAIN` errors.
+pub fn write_vec_slice_offset_flags(
+    file: &std::fs::File,
+    buf: &[u8],
+    chunk_size: u64,
+    mut offset: off_t,
+    flags: c_int,
+) -> std::io::Result<u64> {
+    let fd = file.as_raw_fd();
+    let mut iovecs = iovecs_of(buf, chunk_size);
+    let mut first = 0;
+    let mut eagain = 0;
+    let mut f = flags;
+    while first < iovecs.len() {
+        let b = unsafe {
+            pwritev2(
+                fd,
+                iovecs[first..].as_ptr(),
+                (iovecs.len() - first).min(IOV_MAX) as c_int,
+                offset,
+                f,
+            )
+        };
+        if b < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::WouldBlock && f & RWF_NOWAIT != 0 {
+                eagain += 1;
+                f = flags & !RWF_NOWAIT;
+                continue;
+            }
+            return Err(err);
+        }
+        if b == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::WriteZero));
+        }
+        f = flags;
+        advance(&mut iovecs, &mut first, b as usize);
+        offset += b;
+    }
+    Ok(eagain)
+}
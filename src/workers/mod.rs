@@ -0,0 +1,444 @@
+//! Spawning, placement, synchronised start and timing of the worker threads of
+//! the parallel engines.
+use crate::affinity::*;
+use crate::config::ParConfig;
+use crate::utility::option_enum;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::{Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Barrier};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+//-----------------------------------------------------------------------------
+/// Start and end of a worker's measured section, relative to a common epoch.
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadTiming {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl ThreadTiming {
+    pub fn elapsed(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Timings of all the workers of one parallel run.
+#[derive(Clone, Debug)]
+pub struct ParTiming {
+    pub threads: Vec<ThreadTiming>,
+    /// Engine specific event counts, e.g. `("EAGAIN", n)`.
+    pub counters: Vec<(&'static str, u64)>,
+    /// Time spent populating mappings before the workers start, mmap engines
+    /// only.
+    pub prefault: Option<Duration>,
+    /// Time spent writing back dirty pages, mmap writers and copies only.
+    pub writeback: Option<Duration>,
+    /// Chunked digest of the data when hashed, see `Digests::chunked_digest`.
+    pub digest: Option<String>,
+    /// Time spent after the workers end, e.g. syncing the files, counted in
+    /// `elapsed`.
+    pub after: Duration,
+}
+
+impl ParTiming {
+    /// Aggregate time: first start to last end, plus the time spent after the
+    /// workers end.
+    pub fn elapsed(&self) -> Duration {
+        let b = self
+            .threads
+            .iter()
+            .map(|t| t.start)
+            .min()
+            .unwrap_or_default();
+        let e = self.threads.iter().map(|t| t.end).max().unwrap_or_default();
+        e - b + self.after
+    }
+    /// Time taken by the slowest worker.
+    pub fn slowest(&self) -> Duration {
+        self.threads
+            .iter()
+            .map(|t| t.elapsed())
+            .max()
+            .unwrap_or_default()
+    }
+    /// Time between the first and the last worker leaving the barrier.
+    pub fn start_skew(&self) -> Duration {
+        spread(self.threads.iter().map(|t| t.start))
+    }
+    /// Time between the first and the last worker finishing.
+    pub fn end_skew(&self) -> Duration {
+        spread(self.threads.iter().map(|t| t.end))
+    }
+}
+
+fn spread<I: Iterator<Item = Duration> + Clone>(i: I) -> Duration {
+    match (i.clone().min(), i.max()) {
+        (Some(b), Some(e)) => e - b,
+        _ => Duration::default(),
+    }
+}
+
+/// Print throughput of a parallel run together with the per-thread statistics
+/// and the engine specific counters.
+pub fn print_par_timing(name: &str, size_gib: f64, timing: &ParTiming) {
+    let counters: String = timing
+        .counters
+        .iter()
+        .map(|(n, v)| format!(", {}: {}", n, v))
+        .chain(
+            timing
+                .prefault
+                .map(|p| format!(", prefault: {:.3} s", p.as_secs_f64())),
+        )
+        .chain(
+            timing
+                .writeback
+                .map(|w| format!(", writeback: {:.3} s", w.as_secs_f64())),
+        )
+        .collect();
+    println!(
+        "{:<32} {:.2} GiB/s, aggregate: {:.3} s, slowest thread: {:.3} s, skew: {:.3} ms (start), {:.3} ms (end){}",
+        format!("{}:", name),
+        size_gib / timing.elapsed().as_secs_f64(),
+        timing.elapsed().as_secs_f64(),
+        timing.slowest().as_secs_f64(),
+        timing.start_skew().as_secs_f64() * 1000.,
+        timing.end_skew().as_secs_f64() * 1000.,
+        counters
+    );
+    if let Some(d) = &timing.digest {
+        println!("{:<32} {}", "  chunked digest:", d);
+    }
+}
+
+//-----------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Workers are threads sharing the address space of the benchmark.
+    Threads,
+    /// Workers are forked processes, each with its own copy of the address
+    /// space: data read into the buffer is not visible to the parent. Only a
+    /// single-threaded benchmark forks safely: no thread pool, no POSIX AIO.
+    Processes,
+}
+
+option_enum!(Mode, "worker mode", {
+    "threads" => Threads,
+    "processes" => Processes,
+});
+
+//-----------------------------------------------------------------------------
+/// Start barrier: a `Barrier` for threads; for processes every worker writes
+/// one byte to `ready`, closes it and blocks reading `go` until the parent
+/// closes it.
+#[derive(Clone)]
+enum Gate {
+    Barrier(Arc<Barrier>),
+    Pipe { ready: RawFd, go: RawFd },
+}
+
+impl Gate {
+    fn wait(&self) {
+        match self {
+            Gate::Barrier(b) => {
+                b.wait();
+            }
+            Gate::Pipe { ready, go } => unsafe {
+                let mut b = 0u8;
+                libc::write(*ready, &b as *const u8 as *const libc::c_void, 1);
+                // the parent waits for end of file, i.e. for all the children
+                // to either get here or exit
+                libc::close(*ready);
+                libc::read(*go, &mut b as *mut u8 as *mut libc::c_void, 1);
+            },
+        }
+    }
+}
+
+/// Create a pipe, returns the `(read, write)` ends.
+fn pipe() -> std::io::Result<(RawFd, RawFd)> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(IOError::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Fail when some variant forks the workers: `engine` leaves helper threads
+/// running in the benchmark and the children may inherit locks they hold.
+pub fn threads_only(cfg: &ParConfig, engine: &str) -> std::io::Result<()> {
+    if cfg.variants.iter().any(|v| v.mode == Mode::Processes) {
+        return Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            format!("{} cannot be combined with --mode=processes", engine).as_str(),
+        ));
+    }
+    Ok(())
+}
+
+//-----------------------------------------------------------------------------
+/// Per-worker context handed to the thread body.
+pub struct Worker {
+    pub index: u64,
+    node: Option<usize>,
+    numa: NumaPolicy,
+    gate: Gate,
+    epoch: Instant,
+    start: Cell<Option<Duration>>,
+}
+
+impl Worker {
+    /// Place a slice the worker writes into, i.e. a read destination.
+    pub fn place_mut(&self, buf: &mut [u8]) -> std::io::Result<()> {
+        match (self.numa, self.node) {
+            (NumaPolicy::FirstTouch, _) => {
+                first_touch(buf);
+                Ok(())
+            }
+            (NumaPolicy::Bind, Some(n)) => bind_to_node(buf, n),
+            _ => Ok(()),
+        }
+    }
+    /// Place a slice the worker only reads from, i.e. a write source: the data is
+    /// already initialised so first-touch falls back to binding.
+    pub fn place(&self, buf: &[u8]) -> std::io::Result<()> {
+        match (self.numa, self.node) {
+            (NumaPolicy::None, _) | (_, None) => Ok(()),
+            (_, Some(n)) => bind_to_node(buf, n),
+        }
+    }
+    /// Wait for all the workers to complete their setup, then start the clock.
+    /// Everything before this call is not measured.
+    pub fn start(&self) {
+        if self.start.get().is_none() {
+            self.gate.wait();
+            self.start.set(Some(self.epoch.elapsed()));
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+enum Handle {
+    Thread(JoinHandle<std::io::Result<ThreadTiming>>),
+    Pool(Receiver<std::thread::Result<std::io::Result<ThreadTiming>>>),
+    /// Child pid and read end of the pipe the child reports its timing on.
+    Process(libc::pid_t, RawFd),
+    Failed(IOError),
+}
+
+/// Pipe ends of the process start gate kept by the parent.
+struct GatePipes {
+    ready: (RawFd, RawFd),
+    go: (RawFd, RawFd),
+}
+
+pub struct Workers<'a> {
+    cfg: &'a ParConfig,
+    gate: Gate,
+    pipes: Option<GatePipes>,
+    epoch: Instant,
+    threads: Vec<Handle>,
+}
+
+impl<'a> Workers<'a> {
+    /// `num_threads` workers must be spawned, they all meet at the start barrier.
+    /// In process mode the workers are forked instead of spawned, the thread
+    /// pool is not used.
+    pub fn new(cfg: &'a ParConfig, num_threads: u64) -> std::io::Result<Self> {
+        let pipes = match cfg.variant.mode {
+            Mode::Threads => None,
+            Mode::Processes => {
+                let ready = pipe()?;
+                let go = pipe().inspect_err(|_| unsafe {
+                    libc::close(ready.0);
+                    libc::close(ready.1);
+                })?;
+                Some(GatePipes { ready, go })
+            }
+        };
+        let gate = match &pipes {
+            Some(p) => Gate::Pipe {
+                ready: p.ready.1,
+                go: p.go.0,
+            },
+            None => Gate::Barrier(Arc::new(Barrier::new(num_threads as usize))),
+        };
+        Ok(Workers {
+            cfg,
+            gate,
+            pipes,
+            epoch: Instant::now(),
+            threads: Vec::new(),
+        })
+    }
+
+    /// Spawn worker `index`, pinned to its cpu if requested. Binding to a node
+    /// requires pinning, an unpinned worker has no local node.
+    /// The body must call `Worker::start` once its setup is done.
+    /// With a thread pool configured the worker runs on pool thread `index`
+    /// instead of a new thread; an unpinned worker gets the process affinity back.
+    pub fn spawn<F>(&mut self, index: u64, f: F)
+    where
+        F: FnOnce(&Worker) -> std::io::Result<()> + Send + 'static,
+    {
+        let cpu = self.cfg.cpu(index);
+        let worker = Worker {
+            index,
+            node: cpu.and_then(|c| node_of_cpu(&self.cfg.nodes, c)),
+            numa: self.cfg.numa,
+            gate: self.gate.clone(),
+            epoch: self.epoch,
+            start: Cell::new(None),
+        };
+        // pool threads keep the pinning of previous runs unless reset
+        let unpin = match (cpu, &self.cfg.pool) {
+            (None, Some(_)) => Some(initial_affinity()),
+            _ => None,
+        };
+        let body = move || {
+            let ret = catch_unwind(AssertUnwindSafe(|| match (cpu, unpin) {
+                (Some(c), _) => pin_thread(c).and_then(|_| f(&worker)),
+                (None, Some(set)) => unpin_thread(&set).and_then(|_| f(&worker)),
+                (None, None) => f(&worker),
+            }));
+            // a worker failing or panicking during setup still has to release
+            // the others, the panic is then passed on to `join`
+            worker.start();
+            let ret = ret.unwrap_or_else(|e| resume_unwind(e));
+            let end = worker.epoch.elapsed();
+            ret?;
+            Ok(ThreadTiming {
+                start: worker.start.get().unwrap_or(end),
+                end,
+            })
+        };
+        if self.cfg.variant.mode == Mode::Processes {
+            let th = self.fork(body);
+            self.threads.push(th);
+            return;
+        }
+        let th = match &self.cfg.pool {
+            Some(pool) => {
+                let (tx, rx) = channel();
+                pool.execute(index as usize, move || {
+                    let _ = tx.send(catch_unwind(AssertUnwindSafe(body)));
+                });
+                Handle::Pool(rx)
+            }
+            None => Handle::Thread(std::thread::spawn(body)),
+        };
+        self.threads.push(th);
+    }
+
+    /// Run `body` in a child process, which reports `start end` in nanoseconds
+    /// or an error message to the parent through a pipe.
+    fn fork<F>(&self, body: F) -> Handle
+    where
+        F: FnOnce() -> std::io::Result<ThreadTiming>,
+    {
+        let pipes = self
+            .pipes
+            .as_ref()
+            .expect("process mode without start gate pipes");
+        let (rr, rw) = match pipe() {
+            Ok(p) => p,
+            Err(e) => return Handle::Failed(e),
+        };
+        match unsafe { libc::fork() } {
+            -1 => Handle::Failed(IOError::last_os_error()),
+            0 => {
+                unsafe {
+                    libc::close(rr);
+                    libc::close(pipes.ready.0);
+                    libc::close(pipes.go.1);
+                }
+                // never unwind into the parent's code
+                let msg = match catch_unwind(AssertUnwindSafe(body)) {
+                    Ok(Ok(t)) => format!("{} {}", t.start.as_nanos(), t.end.as_nanos()),
+                    Ok(Err(e)) => format!("error: {}", e),
+                    Err(e) => format!("panic: {:?}", e),
+                };
+                let mut f = unsafe { File::from_raw_fd(rw) };
+                let _ = f.write_all(msg.as_bytes());
+                unsafe { libc::_exit(0) }
+            }
+            pid => {
+                unsafe { libc::close(rw) };
+                Handle::Process(pid, rr)
+            }
+        }
+    }
+
+    /// Release the forked workers once all of them are ready, i.e. all the write
+    /// ends of `ready` are closed: closing the write end of `go` then wakes up
+    /// every child at once.
+    fn open_gate(&self) {
+        if let Some(p) = &self.pipes {
+            unsafe {
+                libc::close(p.ready.1);
+                libc::close(p.go.0);
+            }
+            let mut ready = unsafe { File::from_raw_fd(p.ready.0) };
+            let _ = ready.read_to_end(&mut Vec::new());
+            unsafe { libc::close(p.go.1) };
+        }
+    }
+
+    pub fn join(self) -> std::io::Result<ParTiming> {
+        self.open_gate();
+        let mut timing = ParTiming {
+            threads: Vec::new(),
+            counters: Vec::new(),
+            prefault: None,
+            writeback: None,
+            digest: None,
+            after: Duration::ZERO,
+        };
+        for t in self.threads {
+            let ret = match t {
+                Handle::Thread(th) => th.join(),
+                Handle::Pool(rx) => rx.recv().unwrap_or_else(|e| Err(Box::new(e))),
+                Handle::Process(pid, fd) => Ok(wait_child(pid, fd)),
+                Handle::Failed(e) => Ok(Err(e)),
+            };
+            match ret {
+                Err(e) => {
+                    return Err(IOError::other(
+                        format!("Error joining thread: {:?}", e).as_str(),
+                    ))
+                }
+                Ok(ret) => timing.threads.push(ret?),
+            }
+        }
+        Ok(timing)
+    }
+}
+
+/// Collect the report of a forked worker and reap it.
+fn wait_child(pid: libc::pid_t, fd: RawFd) -> std::io::Result<ThreadTiming> {
+    let mut msg = String::new();
+    let ret = unsafe { File::from_raw_fd(fd) }.read_to_string(&mut msg);
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    ret?;
+    let t: Vec<u64> = msg
+        .split(' ')
+        .filter_map(|v| v.parse::<u64>().ok())
+        .collect();
+    match t[..] {
+        [start, end] => Ok(ThreadTiming {
+            start: Duration::from_nanos(start),
+            end: Duration::from_nanos(end),
+        }),
+        _ => Err(IOError::other(
+            format!("Worker process {}: {}", pid, msg).as_str(),
+        )),
+    }
+}
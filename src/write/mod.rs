@@ -1,21 +1,35 @@
 //! Write to file using a variety of APIs.
-//use glommio::{io::BufferedFile, LocalExecutor};
-use memmap2::MmapOptions;
+use crate::config::{Flush, MmapConfig};
+use crate::digest::Digests;
+use crate::utility::{check_dio_align, flush_range, map_file_mut, MmapTiming, Ring};
+#[cfg(feature = "seq_glommio_write")]
+use glommio::io::BufferedFile;
+#[cfg(any(feature = "seq_glommio_write", feature = "seq_glommio_dma_write"))]
+use glommio::LocalExecutor;
 use std::io::Write;
 use std::time::{Duration, Instant};
 use std::{fs::OpenOptions, os::unix::fs::OpenOptionsExt};
 //-----------------------------------------------------------------------------
-pub fn seq_write(fname: &str, chunk_size: u64, num_chunks: u64) -> std::io::Result<Duration> {
+pub fn seq_write(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    digests: &Digests,
+) -> std::io::Result<Duration> {
     let mut file = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
     let buf = vec![0_u8; chunk_size as usize];
     let t = Instant::now();
-    for _ in 0..num_chunks {
+    for c in 0..num_chunks {
         file.write_all(&buf)?;
+        digests.record(c * chunk_size, &buf);
     }
     file.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(chunk_size * num_chunks)?;
     let e = t.elapsed();
     Ok(e)
 }
@@ -25,19 +39,28 @@ pub fn seq_write_all(
     chunk_size: u64,
     num_chunks: u64,
     filebuf: &[u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
     let mut file = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
+    let ring = Ring::new(filebuf.len(), chunk_size * num_chunks, chunk_size)?;
     let mut r = 0_u64;
     let t = Instant::now();
     for _ in 0..num_chunks {
         let b = r as usize;
-        let e = b + (chunk_size as usize);
-        r += file.write(&filebuf[b..e])? as u64;
+        let e = (b / chunk_size as usize + 1) * chunk_size as usize;
+        r += file.write(&filebuf[ring.slot(b..e)])? as u64;
+        if r as usize == e {
+            let c = e - chunk_size as usize;
+            digests.record(c as u64, &filebuf[ring.slot(c..e)]);
+        }
     }
     file.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(chunk_size * num_chunks)?;
     let e = t.elapsed();
     Ok(e)
 }
@@ -47,44 +70,58 @@ pub fn seq_write_direct_all(
     chunk_size: u64,
     num_chunks: u64,
     filebuf: &[u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
-    if chunk_size % 512 != 0 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "O_DIRECT requires a chunk size multiple of 512'",
-        ));
-    }
+    // the file holds whole chunks of an aligned size: no unaligned tail
+    check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
     let mut r = 0_u64;
     let mut file = OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
         .custom_flags(libc::O_DIRECT)
         .open(fname)?;
     let fsize = num_chunks * chunk_size;
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let t = Instant::now();
-    for _ in 0..num_chunks {
+    while r < fsize {
         let b = r as usize;
-        let e = (b + (chunk_size as usize)).min(fsize as usize);
-        r += file.write(&filebuf[b..e])? as u64;
+        let e = (b / chunk_size as usize + 1) * chunk_size as usize;
+        r += file.write(&filebuf[ring.slot(b..e)])? as u64;
+        if r as usize == e {
+            let c = e - chunk_size as usize;
+            digests.record(c as u64, &filebuf[ring.slot(c..e)]);
+        }
     }
     file.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(fsize)?;
     let e = t.elapsed();
     Ok(e)
 }
 
 //-----------------------------------------------------------------------------
-pub fn seq_write_buf(fname: &str, chunk_size: u64, num_chunks: u64) -> std::io::Result<Duration> {
+pub fn seq_write_buf(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    digests: &Digests,
+) -> std::io::Result<Duration> {
     let file = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
     let buf = vec![0_u8; chunk_size as usize];
     let mut br = std::io::BufWriter::new(file);
     let t = Instant::now();
-    for _ in 0..num_chunks {
+    for c in 0..num_chunks {
         br.write_all(&buf)?;
+        digests.record(c * chunk_size, &buf);
     }
     br.flush()?;
+    // drop stale data of a previous, longer file
+    br.get_ref().set_len(chunk_size * num_chunks)?;
     let e = t.elapsed();
     Ok(e)
 }
@@ -94,45 +131,45 @@ pub fn seq_write_buf_all(
     chunk_size: u64,
     num_chunks: u64,
     filebuf: &[u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
     let mut r = 0_u64;
     let file = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
     let fsize = chunk_size * num_chunks;
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
     let mut br = std::io::BufWriter::new(&file);
     let t = Instant::now();
     for _ in 0..num_chunks {
         let b = r as usize;
-        let e = (b + (chunk_size as usize)).min(fsize as usize);
-        r += br.write(&filebuf[b..e])? as u64;
+        let e = ((b / chunk_size as usize + 1) * chunk_size as usize).min(fsize as usize);
+        r += br.write(&filebuf[ring.slot(b..e)])? as u64;
+        if r as usize == e {
+            let c = b / chunk_size as usize * chunk_size as usize;
+            digests.record(c as u64, &filebuf[ring.slot(c..e)]);
+        }
     }
     br.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(fsize)?;
     let e = t.elapsed();
     Ok(e)
 }
 //-----------------------------------------------------------------------------
-pub fn seq_write_mmap(fname: &str, chunk_size: u64, num_chunks: u64) -> std::io::Result<Duration> {
-    let mut file = std::fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(fname)?;
-    let fsize = num_chunks * chunk_size;
+// the file is extended to its final size before mapping, time spent flushing
+// the mapping is reported as writeback
+pub fn seq_write_mmap(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    cfg: &MmapConfig,
+    digests: &Digests,
+) -> std::io::Result<MmapTiming> {
     let buf = vec![0_u8; chunk_size as usize];
-    let mut mmap = unsafe { MmapOptions::new().len(fsize as usize).map_mut(&file)? };
-    let mut r = 0_u64;
-    let t = Instant::now();
-    for _ in 0..num_chunks {
-        let b = r as usize;
-        let e = b + buf.len();
-        mmap[b..e].copy_from_slice(&buf);
-        r += chunk_size;
-    }
-    file.flush()?;
-    let e = t.elapsed();
-    Ok(e)
+    write_mmap(fname, chunk_size, num_chunks, cfg, digests, |_| &buf[..])
 }
 //-----------------------------------------------------------------------------
 pub fn seq_write_mmap_all(
@@ -140,53 +177,169 @@ pub fn seq_write_mmap_all(
     chunk_size: u64,
     num_chunks: u64,
     filebuf: &[u8],
-) -> std::io::Result<Duration> {
-    let mut r = 0_u64;
-    let mut file = std::fs::OpenOptions::new()
+    cfg: &MmapConfig,
+    digests: &Digests,
+) -> std::io::Result<MmapTiming> {
+    let ring = Ring::new(filebuf.len(), chunk_size * num_chunks, chunk_size)?;
+    write_mmap(fname, chunk_size, num_chunks, cfg, digests, |r| {
+        &filebuf[ring.slot(r)]
+    })
+}
+
+// copy `src(range)` into each chunk of the mapping
+fn write_mmap<'a, F: Fn(std::ops::Range<usize>) -> &'a [u8]>(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    cfg: &MmapConfig,
+    digests: &Digests,
+    src: F,
+) -> std::io::Result<MmapTiming> {
+    let file = std::fs::OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
     let fsize = num_chunks * chunk_size;
-    let mut mmap = unsafe { MmapOptions::new().len(fsize as usize).map_mut(&file)? };
+    file.set_len(fsize)?;
+    // an empty file cannot be mapped
+    if fsize == 0 {
+        return Ok(MmapTiming {
+            prefault: Duration::ZERO,
+            copy: Duration::ZERO,
+            writeback: Duration::ZERO,
+        });
+    }
+    let (mut mmap, prefault) = map_file_mut(&file, fsize as usize, cfg)?;
+    let mut writeback = Duration::ZERO;
+    let mut r = 0_u64;
     let t = Instant::now();
     for _ in 0..num_chunks {
         let b = r as usize;
         let e = b + (chunk_size as usize);
-        mmap[b..e].copy_from_slice(&filebuf[b..e]);
+        mmap[b..e].copy_from_slice(src(b..e));
+        digests.record(r, src(b..e));
+        if cfg.flush == Flush::Chunk {
+            writeback += flush_range(&mmap, b, e - b, cfg)?;
+        }
         r += chunk_size;
     }
-    file.flush()?;
-    let e = t.elapsed();
-    Ok(e)
+    if cfg.flush != Flush::Chunk {
+        writeback += flush_range(&mmap, 0, fsize as usize, cfg)?;
+    }
+    let copy = t.elapsed() - writeback;
+    Ok(MmapTiming {
+        prefault,
+        copy,
+        writeback,
+    })
 }
 //-----------------------------------------------------------------------------
 pub fn seq_write_vec_all(
     fname: &str,
     chunk_size: u64,
+    num_chunks: u64,
     filebuf: &[u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
     let mut file = std::fs::OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
+    let fsize = chunk_size * num_chunks;
+    let ring = Ring::new(filebuf.len(), fsize, chunk_size)?;
+    let mut r = 0_u64;
     let t = Instant::now();
     use crate::vec_io;
-    vec_io::write_vec_slice(&mut file, filebuf, chunk_size)?;
+    // one vectored write per pass over the ring
+    while r < fsize {
+        let len = (ring.capacity() as u64).min(fsize - r);
+        vec_io::write_vec_slice(&mut file, &filebuf[..len as usize], chunk_size)?;
+        digests.record(r, &filebuf[..len as usize]);
+        r += len;
+    }
     file.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(fsize)?;
     let e = t.elapsed();
     Ok(e)
 }
+//-----------------------------------------------------------------------------
+// buffers are moved into glommio, the time includes copying each chunk
+#[cfg(feature = "seq_glommio_write")]
+pub fn seq_glommio_write(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    filebuf: &[u8],
+    digests: &Digests,
+) -> std::io::Result<Duration> {
+    let ring = Ring::new(filebuf.len(), chunk_size * num_chunks, chunk_size)?;
+    let ex = LocalExecutor::default();
+    ex.run(async {
+        let file = BufferedFile::create(fname).await?;
+        let t = Instant::now();
+        for c in 0..num_chunks {
+            let b = (c * chunk_size) as usize;
+            let e = b + chunk_size as usize;
+            file.write_at(filebuf[ring.slot(b..e)].to_vec(), b as u64)
+                .await?;
+            digests.record(b as u64, &filebuf[ring.slot(b..e)]);
+        }
+        file.close().await?;
+        let e = t.elapsed();
+        Ok(e)
+    })
+}
+//-----------------------------------------------------------------------------
+// buffers are moved into glommio, the time includes copying each chunk
+#[cfg(feature = "seq_glommio_dma_write")]
+pub fn seq_glommio_dma_write(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    filebuf: &[u8],
+    digests: &Digests,
+) -> std::io::Result<Duration> {
+    use glommio::io::DmaFile;
+    check_dio_align(fname, None, chunk_size, None)?;
+    let ring = Ring::new(filebuf.len(), chunk_size * num_chunks, chunk_size)?;
+    let ex = LocalExecutor::default();
+    ex.run(async {
+        let file = DmaFile::create(fname).await?;
+        let t = Instant::now();
+        for c in 0..num_chunks {
+            let b = (c * chunk_size) as usize;
+            let e = b + chunk_size as usize;
+            let mut buf = file.alloc_dma_buffer(e - b);
+            buf.as_bytes_mut()
+                .copy_from_slice(&filebuf[ring.slot(b..e)]);
+            file.write_at(buf, b as u64).await?;
+            digests.record(b as u64, &filebuf[ring.slot(b..e)]);
+        }
+        file.close().await?;
+        let e = t.elapsed();
+        Ok(e)
+    })
+}
 
 //-----------------------------------------------------------------------------
 // @warning will normally fail for total size > (2GiB - 4kiB), limit imposed
 // by vectored i/o, so partial reads/writes must be handled
 #[cfg(all(feature = "seq_write_uring_all", target_os = "linux"))]
-pub fn seq_write_uring_all(fname: &str, chunk_size: u64, num_chunks: u64) -> std::io::Result<Duration> {
+pub fn seq_write_uring_all(
+    fname: &str,
+    chunk_size: u64,
+    num_chunks: u64,
+    digests: &Digests,
+) -> std::io::Result<Duration> {
     let mut file = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(false)
         .open(fname)?;
     let buf = vec![0_u8; (chunk_size * num_chunks) as usize];
     let t = Instant::now();
@@ -215,7 +368,10 @@ pub fn seq_write_uring_all(fname: &str, chunk_size: u64, num_chunks: u64) -> std
             format!("seq_write_uring_all: Failed to write data from io_uring queue, requested: {}, written: {}", chunk_size * num_chunks, n).as_str())
         );
     }
+    digests.record(0, &buf);
     file.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(chunk_size * num_chunks)?;
     let e = t.elapsed();
     Ok(e)
 }
@@ -229,24 +385,31 @@ pub fn seq_write_uring_vec_all(
     chunk_size: u64,
     num_chunks: u64,
     filebuf: &[u8],
+    digests: &Digests,
 ) -> std::io::Result<Duration> {
-    // @todo: check for alignment
+    if cfg!(feature = "uring_direct") {
+        check_dio_align(fname, Some(filebuf.as_ptr()), chunk_size, None)?;
+    }
     let mut file = if cfg!(feature = "uring_direct") {
         std::fs::OpenOptions::new()
             .write(true)
             .custom_flags(libc::O_DIRECT)
             .create(true)
+            .truncate(false)
             .open(fname)?
     } else {
         std::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(fname)?
     };
+    let ring = Ring::new(filebuf.len(), chunk_size * num_chunks, chunk_size)?;
+    // with a ring the same buffers appear more than once in the vector
     let mut bufs = Vec::new();
     for c in 0..num_chunks {
         let i = c as usize * chunk_size as usize;
-        let s = &filebuf[i..i + chunk_size as usize];
+        let s = &filebuf[ring.slot(i..i + chunk_size as usize)];
         bufs.push(std::io::IoSlice::new(s));
     }
     let t = Instant::now();
@@ -274,7 +437,12 @@ pub fn seq_write_uring_vec_all(
             format!("seq_write_uring_all: Failed to write data from io_uring queue, requested: {}, written: {}", chunk_size * num_chunks, n).as_str()
         ));
     }
+    for (c, b) in bufs.iter().enumerate() {
+        digests.record(c as u64 * chunk_size, b);
+    }
     file.flush()?;
+    // drop stale data of a previous, longer file
+    file.set_len(num_chunks * chunk_size)?;
     let e = t.elapsed();
     Ok(e)
 }